@@ -0,0 +1,252 @@
+// abilities - DnDice
+//   URL: https://github.com/pennbauman/dndice-rs
+//   Author:
+//     Penn Bauman (pennbauman@protonmail.com)
+use std::fmt;
+use num_integer::Integer;
+use crate::scores::{Scores, ScoresError};
+
+
+/// The six abilities scores are assigned to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Ability {
+    Str,
+    Dex,
+    Con,
+    Int,
+    Wis,
+    Cha,
+}
+impl Ability {
+    /// Every ability, in the order they are usually listed
+    pub const ALL: [Ability; 6] = [Self::Str, Self::Dex, Self::Con, Self::Int, Self::Wis, Self::Cha];
+
+    /// Ability from its abbreviation or full name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "str" | "strength" => Some(Self::Str),
+            "dex" | "dexterity" => Some(Self::Dex),
+            "con" | "constitution" => Some(Self::Con),
+            "int" | "intelligence" => Some(Self::Int),
+            "wis" | "wisdom" => Some(Self::Wis),
+            "cha" | "charisma" => Some(Self::Cha),
+            _ => None,
+        }
+    }
+    /// Abbreviation of the ability, like `STR`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Str => "STR",
+            Self::Dex => "DEX",
+            Self::Con => "CON",
+            Self::Int => "INT",
+            Self::Wis => "WIS",
+            Self::Cha => "CHA",
+        }
+    }
+}
+impl fmt::Display for Ability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+/// Classes with a usual order of importance of their abilities
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Class {
+    Barbarian,
+    Bard,
+    Cleric,
+    Druid,
+    Fighter,
+    Monk,
+    Paladin,
+    Ranger,
+    Rogue,
+    Sorcerer,
+    Warlock,
+    Wizard,
+}
+impl Class {
+    /// Class from its name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "barbarian" => Some(Self::Barbarian),
+            "bard" => Some(Self::Bard),
+            "cleric" => Some(Self::Cleric),
+            "druid" => Some(Self::Druid),
+            "fighter" => Some(Self::Fighter),
+            "monk" => Some(Self::Monk),
+            "paladin" => Some(Self::Paladin),
+            "ranger" => Some(Self::Ranger),
+            "rogue" => Some(Self::Rogue),
+            "sorcerer" => Some(Self::Sorcerer),
+            "warlock" => Some(Self::Warlock),
+            "wizard" => Some(Self::Wizard),
+            _ => None,
+        }
+    }
+    /// Abilities of the class from most to least important
+    pub fn priority(&self) -> [Ability; 6] {
+        use Ability::*;
+        match self {
+            Self::Barbarian => [Str, Con, Dex, Wis, Cha, Int],
+            Self::Bard => [Cha, Dex, Con, Wis, Int, Str],
+            Self::Cleric => [Wis, Con, Str, Dex, Cha, Int],
+            Self::Druid => [Wis, Con, Dex, Int, Cha, Str],
+            Self::Fighter => [Str, Con, Dex, Wis, Cha, Int],
+            Self::Monk => [Dex, Wis, Con, Str, Int, Cha],
+            Self::Paladin => [Str, Cha, Con, Wis, Dex, Int],
+            Self::Ranger => [Dex, Wis, Con, Str, Int, Cha],
+            Self::Rogue => [Dex, Con, Wis, Int, Cha, Str],
+            Self::Sorcerer => [Cha, Con, Dex, Wis, Int, Str],
+            Self::Warlock => [Cha, Con, Dex, Wis, Int, Str],
+            Self::Wizard => [Int, Con, Dex, Wis, Cha, Str],
+        }
+    }
+}
+
+
+/// Order to assign scores to abilities, highest score first, with bonuses added afterward, such
+/// as the +2 and +1 from a race or background
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    order: [Ability; 6],
+    bonuses: [i8; 6],
+}
+impl Assignment {
+    /// Assign scores in an order, failing unless it has every ability once
+    pub fn new(order: &[Ability]) -> Result<Self, ScoresError> {
+        let invalid = || ScoresError::InvalidOrder(order.iter().map(|a| a.name())
+            .collect::<Vec<&str>>().join(" "));
+        let order: [Ability; 6] = order.try_into().map_err(|_| invalid())?;
+        if Ability::ALL.iter().any(|a| !order.contains(a)) {
+            return Err(invalid());
+        }
+        return Ok(Self { order, bonuses: [0; 6] });
+    }
+    /// Assign scores in the order of importance for a class
+    pub fn class(class: Class) -> Self {
+        Self { order: class.priority(), bonuses: [0; 6] }
+    }
+    /// Abilities in the order scores are assigned
+    pub fn order(&self) -> &[Ability; 6] {
+        &self.order
+    }
+    /// Add a bonus, or a penalty if negative, to an ability's score
+    pub fn bonus(&mut self, ability: Ability, amount: i8) {
+        let b = &mut self.bonuses[ability as usize];
+        *b = b.saturating_add(amount);
+    }
+    /// Assign scores to abilities, keeping each from 1 to 30 after its bonus
+    pub fn apply(&self, scores: &Scores) -> Abilities {
+        let mut nums = [0; 6];
+        for (a, s) in self.order.iter().zip(scores.values()) {
+            let i = *a as usize;
+            nums[i] = (i16::from(*s) + i16::from(self.bonuses[i])).clamp(1, 30) as u8;
+        }
+        return Abilities { nums };
+    }
+}
+impl Default for Assignment {
+    fn default() -> Self {
+        Self { order: Ability::ALL, bonuses: [0; 6] }
+    }
+}
+
+
+/// Scores assigned to each ability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Abilities {
+    nums: [u8; 6],
+}
+impl Abilities {
+    /// Modifier of a score, half of its difference from 10 rounded down
+    pub fn modifier_of(score: u8) -> i8 {
+        Integer::div_floor(&(i16::from(score) - 10), &2) as i8
+    }
+    /// Score of an ability
+    pub fn score(&self, ability: Ability) -> u8 {
+        self.nums[ability as usize]
+    }
+    /// Modifier of an ability
+    pub fn modifier(&self, ability: Ability) -> i8 {
+        Self::modifier_of(self.score(ability))
+    }
+    /// Every ability with its score and modifier, in the order abilities are usually listed
+    pub fn iter(&self) -> impl Iterator<Item = (Ability, u8, i8)> + '_ {
+        Ability::ALL.into_iter().map(|a| (a, self.score(a), self.modifier(a)))
+    }
+}
+impl fmt::Display for Abilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (a, score, modifier)) in self.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} {:2} ({:+})", a, score, modifier)?;
+        }
+        return Ok(());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ability_name() {
+        for a in Ability::ALL {
+            assert_eq!(Ability::from_name(a.name()), Some(a));
+        }
+        assert_eq!(Ability::from_name("Wisdom"), Some(Ability::Wis));
+        assert_eq!(Ability::from_name("luck"), None);
+    }
+    #[test]
+    fn test_class_priority() {
+        assert_eq!(Class::from_name("Wizard"), Some(Class::Wizard));
+        assert_eq!(Class::from_name("artificer"), None);
+        for name in ["barbarian", "bard", "cleric", "druid", "fighter", "monk", "paladin",
+                "ranger", "rogue", "sorcerer", "warlock", "wizard"] {
+            let order = Class::from_name(name).unwrap().priority();
+            assert!(Assignment::new(&order).is_ok());
+        }
+    }
+    #[test]
+    fn test_abilities_modifier() {
+        assert_eq!(Abilities::modifier_of(1), -5);
+        assert_eq!(Abilities::modifier_of(8), -1);
+        assert_eq!(Abilities::modifier_of(9), -1);
+        assert_eq!(Abilities::modifier_of(10), 0);
+        assert_eq!(Abilities::modifier_of(15), 2);
+        assert_eq!(Abilities::modifier_of(30), 10);
+    }
+    #[test]
+    fn test_assignment_apply() {
+        let a = Assignment::default().apply(&Scores::std());
+        assert_eq!(a.score(Ability::Str), 15);
+        assert_eq!(a.modifier(Ability::Cha), -1);
+        assert_eq!(a.to_string(),
+            "STR 15 (+2)\nDEX 14 (+2)\nCON 13 (+1)\nINT 12 (+1)\nWIS 10 (+0)\nCHA  8 (-1)");
+        let mut assign = Assignment::class(Class::Wizard);
+        assign.bonus(Ability::Int, 2);
+        assign.bonus(Ability::Con, 1);
+        assign.bonus(Ability::Str, -10);
+        let a = assign.apply(&Scores::std());
+        assert_eq!(a.iter().map(|(_, s, _)| s).collect::<Vec<u8>>(), vec![1, 13, 15, 17, 12, 10]);
+        assert_eq!(a.modifier(Ability::Int), 3);
+    }
+    #[test]
+    fn test_assignment_err() {
+        use Ability::*;
+        assert!(Assignment::new(&[Dex, Str, Con, Int, Wis, Cha]).is_ok());
+        assert_eq!(Assignment::new(&[Str, Str, Con, Int, Wis, Cha]),
+            Err(ScoresError::InvalidOrder("STR STR CON INT WIS CHA".to_string())));
+        assert_eq!(Assignment::new(&[Str, Dex]),
+            Err(ScoresError::InvalidOrder("STR DEX".to_string())));
+    }
+}
@@ -0,0 +1,258 @@
+// aliases - DnDice
+//   URL: https://github.com/pennbauman/dndice-rs
+//   Author:
+//     Penn Bauman (pennbauman@protonmail.com)
+use std::fmt;
+use std::str::FromStr;
+use crate::Dice;
+use crate::parse::DiceParseError;
+
+
+/// Error from reading or changing aliases
+#[derive(Debug, PartialEq)]
+pub enum AliasError {
+    /// Line which is not `name = dice`, with its line number
+    InvalidLine(String, usize),
+    InvalidName(String),
+    InvalidDice(String, DiceParseError),
+    Unknown(String),
+}
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLine(s, i) => write!(f, "Invalid line {} '{}'", i, s),
+            Self::InvalidName(s) => write!(f, "Invalid alias name '{}'", s),
+            Self::InvalidDice(s, e) => write!(f, "{} in alias '{}'", e, s),
+            Self::Unknown(s) => write!(f, "Unknown alias '{}'", s),
+        }
+    }
+}
+
+
+/// Named dice expressions, read from and written as a TOML file of `name = "dice"` lines
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Aliases {
+    list: Vec<(String, String)>,
+}
+impl Aliases {
+    pub fn new() -> Self {
+        Self { list: vec![] }
+    }
+    /// Check a name can be used for an alias, starting with a letter or '_' followed by letters,
+    /// numbers, '_' or '-', and not being valid dice itself
+    pub fn valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+            _ => return false,
+        }
+        if !chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return false;
+        }
+        return Dice::from(name).is_err();
+    }
+    /// Dice text of an alias
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.list.iter().find(|(n, _)| n == name).map(|(_, text)| text.as_str())
+    }
+    /// Dice of an alias, named after it
+    pub fn dice(&self, name: &str) -> Option<Dice> {
+        let mut dice = Dice::from(self.get(name)?).ok()?;
+        dice.name(name);
+        return Some(dice);
+    }
+    /// Add an alias, replacing any alias with the same name
+    pub fn add<S: ToString>(&mut self, name: &str, text: S) -> Result<(), AliasError> {
+        if !Self::valid_name(name) {
+            return Err(AliasError::InvalidName(name.to_string()));
+        }
+        let text = text.to_string();
+        if let Err(e) = Dice::from(&text) {
+            return Err(AliasError::InvalidDice(name.to_string(), e));
+        }
+        match self.list.iter_mut().find(|(n, _)| n == name) {
+            Some(alias) => alias.1 = text,
+            None => self.list.push((name.to_string(), text)),
+        }
+        return Ok(());
+    }
+    /// Remove an alias
+    pub fn remove(&mut self, name: &str) -> Result<(), AliasError> {
+        match self.list.iter().position(|(n, _)| n == name) {
+            Some(i) => {
+                self.list.remove(i);
+                return Ok(());
+            },
+            None => return Err(AliasError::Unknown(name.to_string())),
+        }
+    }
+    /// Names and dice text of every alias, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.list.iter().map(|(n, text)| (n.as_str(), text.as_str()))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+impl FromStr for Aliases {
+    type Err = AliasError;
+
+    /// Read aliases from a TOML file of `name = "dice"` lines, ignoring blank lines, `#` comments
+    /// and table headers such as `[aliases]`
+    ///
+    /// Dice may be basic strings in `"` with escapes or literal strings in `'`. As an extension
+    /// quotes are optional, without them the dice continue to the end of the line or a comment.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut aliases = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if is_comment(line) {
+                continue;
+            }
+            let invalid = || AliasError::InvalidLine(line.to_string(), i + 1);
+            if let Some(header) = line.strip_prefix('[') {
+                match header.split_once(']') {
+                    Some((_, rest)) if is_comment(rest) => continue,
+                    _ => return Err(invalid()),
+                }
+            }
+            let (name, rest) = match toml_string(line) {
+                Some((name, rest)) => (name, rest.trim_start().strip_prefix('=').ok_or_else(invalid)?),
+                None => {
+                    let (name, rest) = line.split_once('=').ok_or_else(invalid)?;
+                    (name.trim().to_string(), rest)
+                },
+            };
+            let rest = rest.trim();
+            let text = match toml_string(rest) {
+                Some((text, rest)) if is_comment(rest) => text,
+                Some(_) => return Err(invalid()),
+                None if rest.starts_with(['"', '\'']) => return Err(invalid()),
+                None => rest.split('#').next().unwrap().trim().to_string(),
+            };
+            aliases.add(&name, text)?;
+        }
+        return Ok(aliases);
+    }
+}
+// Check if the rest of a line is empty or a comment
+fn is_comment(text: &str) -> bool {
+    let text = text.trim();
+    return text.is_empty() || text.starts_with('#');
+}
+// Read a TOML basic string with escapes or literal string from the start of text, with the text
+// after it
+fn toml_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, c)) if c == '"' || c == '\'' => c,
+        _ => return None,
+    };
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some((string, &text[i + 1..]));
+        } else if c == '\\' && quote == '"' {
+            string.push(match chars.next()?.1 {
+                '"' => '"',
+                '\\' => '\\',
+                'b' => '\u{8}',
+                't' => '\t',
+                'n' => '\n',
+                'f' => '\u{c}',
+                'r' => '\r',
+                e @ ('u' | 'U') => {
+                    let len = if e == 'u' { 4 } else { 8 };
+                    let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                    if hex.len() != len || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                },
+                _ => return None,
+            });
+        } else {
+            string.push(c);
+        }
+    }
+    return None;
+}
+impl fmt::Display for Aliases {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, text) in &self.list {
+            writeln!(f, "{} = \"{}\"", name, text)?;
+        }
+        return Ok(());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases_valid_name() {
+        assert!(Aliases::valid_name("longsword"));
+        assert!(Aliases::valid_name("long_sword-2"));
+        assert!(Aliases::valid_name("_x"));
+        assert!(!Aliases::valid_name(""));
+        assert!(!Aliases::valid_name("2sword"));
+        assert!(!Aliases::valid_name("long sword"));
+        assert!(!Aliases::valid_name("sword+1"));
+        assert!(!Aliases::valid_name("d20"));
+    }
+    #[test]
+    fn test_aliases_add_remove() {
+        let mut a = Aliases::new();
+        assert!(a.is_empty());
+        a.add("longsword", "1d20+7").unwrap();
+        a.add("longsword_dmg", "1d8+4").unwrap();
+        assert_eq!(a.get("longsword"), Some("1d20+7"));
+        assert_eq!(a.dice("longsword_dmg").unwrap().to_string(), "longsword_dmg: 1d8 + 4");
+        a.add("longsword", "1d20+8").unwrap();
+        assert_eq!(a.iter().collect::<Vec<(&str, &str)>>(),
+            vec![("longsword", "1d20+8"), ("longsword_dmg", "1d8+4")]);
+        a.remove("longsword").unwrap();
+        assert_eq!(a.get("longsword"), None);
+        assert!(a.dice("longsword").is_none());
+        assert_eq!(a.remove("longsword"), Err(AliasError::Unknown("longsword".to_string())));
+        assert_eq!(a.add("d6", "1d6"), Err(AliasError::InvalidName("d6".to_string())));
+        assert_eq!(a.add("bad", "1dd"),
+            Err(AliasError::InvalidDice("bad".to_string(), DiceParseError::InvalidDie("1dd".to_string()))));
+    }
+    #[test]
+    fn test_aliases_parse() {
+        let a: Aliases = "# Attacks\nlongsword = \"1d20+7\"\n\n  longsword_dmg='1d8 + 4'  \n"
+            .parse().unwrap();
+        assert_eq!(a.iter().collect::<Vec<(&str, &str)>>(),
+            vec![("longsword", "1d20+7"), ("longsword_dmg", "1d8 + 4")]);
+        assert_eq!(a.to_string(), "longsword = \"1d20+7\"\nlongsword_dmg = \"1d8 + 4\"\n");
+        assert_eq!(a.to_string().parse::<Aliases>().unwrap(), a);
+        assert_eq!("".parse::<Aliases>().unwrap(), Aliases::new());
+        assert_eq!("a = \"1d4\"\nb 1d4".parse::<Aliases>(),
+            Err(AliasError::InvalidLine("b 1d4".to_string(), 2)));
+        let a: Aliases = "longsword = 1d20+7\nlongsword_dmg=1d8 + 4 \n".parse().unwrap();
+        assert_eq!(a.iter().collect::<Vec<(&str, &str)>>(),
+            vec![("longsword", "1d20+7"), ("longsword_dmg", "1d8 + 4")]);
+        assert_eq!("a = \"1d4".parse::<Aliases>(),
+            Err(AliasError::InvalidLine("a = \"1d4".to_string(), 1)));
+        assert_eq!("a = \"1d4\" + 1".parse::<Aliases>(),
+            Err(AliasError::InvalidLine("a = \"1d4\" + 1".to_string(), 1)));
+        assert_eq!("2a = \"1d4\"".parse::<Aliases>(),
+            Err(AliasError::InvalidName("2a".to_string())));
+    }
+    #[test]
+    fn test_aliases_parse_toml() {
+        let a: Aliases = "[aliases]  # Attacks\nlongsword = \"1d20+7\" # to hit\n\"dagger\" = '1d4' #\n\
+            longsword_dmg = 1d8 + 4 # slashing\nheal = \"2d4\\u002B2\"\n".parse().unwrap();
+        assert_eq!(a.iter().collect::<Vec<(&str, &str)>>(), vec![("longsword", "1d20+7"),
+            ("dagger", "1d4"), ("longsword_dmg", "1d8 + 4"), ("heal", "2d4+2")]);
+        assert_eq!("a = '1d4\\n'".parse::<Aliases>(),
+            Err(AliasError::InvalidDice("a".to_string(), DiceParseError::InvalidChar('\\'))));
+        assert_eq!("a = \"1d4\\q\"".parse::<Aliases>(),
+            Err(AliasError::InvalidLine("a = \"1d4\\q\"".to_string(), 1)));
+        assert_eq!("[aliases".parse::<Aliases>(),
+            Err(AliasError::InvalidLine("[aliases".to_string(), 1)));
+    }
+}
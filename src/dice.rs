@@ -2,369 +2,1292 @@
 //   URL: https://github.com/pennbauman/dndice-rs
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
+use num_integer::Integer;
 use rand::Rng;
-use crate::log::{DiceRoll, RollLog};
-use crate::parse::{ParseKind, ParseState, DiceParseError};
+use crate::log::{DiceRoll, Link, RollLog};
+use crate::parse::{DiceParseError, Parser};
 
 
-// Dice Expression
-#[derive(Debug)]
-pub enum DiceSet {
-    Sum(DiceSeries),
-    Mult(DiceSeries),
+// Precedence of comparisons, dice pools, prefix negation and single terms, used when displaying
+const CHECK_PRECEDENCE: u8 = 0;
+const POOL_PRECEDENCE: u8 = 1;
+const NEG_PRECEDENCE: u8 = 4;
+const TERM_PRECEDENCE: u8 = 5;
+
+/// Default limit on extra dice rolled in a row by one exploding die
+pub const EXPLODE_DEPTH: u32 = 100;
+/// Default limit on times one die is rerolled by a recursive reroll
+pub const REROLL_DEPTH: u32 = 100;
+/// Limit on extra rolls from exploding and rerolling in one set of dice, however many dice it has
+pub const EXTRA_ROLL_LIMIT: u32 = 1000;
+/// Limit on the number of dice rolled in one set
+pub const DICE_LIMIT: u32 = 100_000;
+/// Default lowest natural roll on a d20 which is a critical hit
+pub const CRIT_RANGE: u32 = 20;
+
+
+// Settings used while rolling
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RollOptions {
+    pub explode_depth: u32,
+    pub reroll_depth: u32,
+    pub crit_range: u32,
+}
+impl Default for RollOptions {
+    fn default() -> Self {
+        Self {
+            explode_depth: EXPLODE_DEPTH,
+            reroll_depth: REROLL_DEPTH,
+            crit_range: CRIT_RANGE,
+        }
+    }
+}
+
+
+/// Error from rolling an expression
+#[derive(Debug, PartialEq)]
+pub enum RollError {
+    /// Expression which cannot be rolled as written, like one with a variable without a value
+    Parse(DiceParseError),
+    /// Division by zero, with the division which failed
+    DivideByZero(String),
+    /// Result too large to be calculated, with the part of the expression which failed
+    Overflow(String),
+    /// Dice with more than `DICE_LIMIT` dice or more sides than fit in a `u32`
+    TooManyDice(String),
+}
+impl From<DiceParseError> for RollError {
+    fn from(e: DiceParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::DivideByZero(s) => write!(f, "Division by zero in '{}'", s),
+            Self::Overflow(s) => write!(f, "Result too large in '{}'", s),
+            Self::TooManyDice(s) => write!(f, "Too many dice or sides in '{}'", s),
+        }
+    }
+}
+
+
+/// A dice expression
+///
+/// Expressions are parsed from text with `str::parse` and display in a canonical form which
+/// parses back to the same expression. With the `serde` feature they are serialized as this
+/// canonical form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// A constant number
+    Num(i64),
+    /// A variable, written `@name`, which must be given a value before rolling
+    Var(String),
+    /// A set of dice, rolled and summed
     Die(Die),
-    Const(i64),
+    /// The negation of an expression, written `-x`
+    Neg(Box<Expr>),
+    /// An expression in parentheses
+    Group(Box<Expr>),
+    /// Two expressions combined by an operator
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// A function called with expressions as its arguments, written `name(a, b)`
+    Call(Func, Vec<Expr>),
+    /// A total compared against a target, written `a >= b`, which can only be the whole
+    /// expression and results in the total
+    Check(CmpOp, Box<Expr>, Box<Expr>),
 }
-impl DiceSet {
-    pub fn new() -> Self {
-        Self::Const(0)
-    }
-    pub fn parse(text: &str) -> Result<Self, DiceParseError> {
-        let parser = match ParseState::parse_from(text) {
-            Ok(p) => p,
-            Err(e) => return Err(DiceParseError::InvalidChar(e)),
-        };
-        let splits = parser.splits();
-        match parser.kind() {
-            ParseKind::Sum => {
-                let mut series: DiceSeries = vec![];
-                for s in splits {
-                    if s.0 == '+' {
-                        match SignedDice::parse_pos(&s.1) {
-                            Ok(d) => series.push(d),
-                            Err(e) => return Err(e),
-                        };
-                    } else if s.0 == '-' {
-                        match SignedDice::parse_neg(&s.1) {
-                            Ok(d) => series.push(d),
-                            Err(e) => return Err(e),
-                        };
-                    } else {
-                        panic!("invalid sum");
-                    }
+impl Expr {
+    /// Roll all dice in the expression with a random number generator and calculate its result,
+    /// failing if it has variables, which can be given values by `bind`, or divides by zero
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, RollError> {
+        Ok(self.roll_log(&RollOptions::default(), rng)?.value)
+    }
+    /// Replace every variable with its value from a context, failing if any has no value
+    pub fn bind(&self, context: &HashMap<String, i64>) -> Result<Expr, DiceParseError> {
+        let mut expr = self.clone();
+        expr.bind_mut(context)?;
+        return Ok(expr);
+    }
+    fn bind_mut(&mut self, context: &HashMap<String, i64>) -> Result<(), DiceParseError> {
+        match self {
+            Self::Num(_) => (),
+            Self::Var(name) => match context.get(name) {
+                Some(x) => *self = Self::Num(*x),
+                None => return Err(DiceParseError::UnboundVariable(name.clone())),
+            },
+            Self::Die(d) => {
+                d.number.bind_mut(context)?;
+                d.sides.bind_mut(context)?;
+            },
+            Self::Neg(e) | Self::Group(e) => e.bind_mut(context)?,
+            Self::Binary(_, lhs, rhs) | Self::Check(_, lhs, rhs) => {
+                lhs.bind_mut(context)?;
+                rhs.bind_mut(context)?;
+            },
+            Self::Call(_, args) => {
+                for arg in args {
+                    arg.bind_mut(context)?;
                 }
-                return Ok(DiceSet::Sum(series));
             },
-            ParseKind::Mult => {
-                let mut series: DiceSeries = vec![];
-                for s in splits {
-                    if s.1 == "" {
-                        return Err(DiceParseError::InvalidMath(String::from(text)));
-                    }
-                    let start = s.1.chars().next().unwrap();
-                    if start == '-' {
-                        match SignedDice::parse_neg(&s.1[1..]) {
-                            Ok(d) => series.push(d),
-                            Err(e) => return Err(e),
-                        }
-                    } else {
-                        match SignedDice::parse_pos(&s.1) {
-                            Ok(d) => series.push(d),
-                            Err(e) => return Err(e),
-                        }
-                    }
+        }
+        return Ok(());
+    }
+    /// Names of the variables in the expression, in the order they first appear
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names = vec![];
+        self.find_variables(&mut names);
+        return names;
+    }
+    fn find_variables<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Self::Num(_) => (),
+            Self::Var(name) => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
                 }
-                return Ok(DiceSet::Mult(series));
             },
-            ParseKind::Die => {
-                if splits.len() == 2 {
-                    let mut num = 1;
-                    if splits[0].1 != "" {
-                        num = match splits[0].1.parse::<u32>() {
-                            Ok(i) => i,
-                            Err(_) => return Err(DiceParseError::InvalidNumber(
-                                splits[0].1.to_string()
-                            )),
-                        };
-                    }
-                    let size = match splits[1].1.parse::<u32>() {
-                        Ok(i) => i,
-                        Err(_) => return Err(DiceParseError::InvalidNumber(
-                            splits[1].1.to_string()
-                        )),
-                    };
-                    return Ok(Self::Die(Die::new(num, size)));
-                } else {
-                    println!("'{}' {:?}", text, splits);
-                    return Err(DiceParseError::InvalidDie(String::from(text)));
+            Self::Die(d) => {
+                d.number.find_variables(names);
+                d.sides.find_variables(names);
+            },
+            Self::Neg(e) | Self::Group(e) => e.find_variables(names),
+            Self::Binary(_, lhs, rhs) | Self::Check(_, lhs, rhs) => {
+                lhs.find_variables(names);
+                rhs.find_variables(names);
+            },
+            Self::Call(_, args) => {
+                for arg in args {
+                    arg.find_variables(names);
                 }
             },
-            ParseKind::Const => {
-                match text.parse::<i64>() {
-                    Ok(i) => return Ok(Self::Const(i)),
-                    Err(_) => return Err(DiceParseError::InvalidNumber(
-                            String::from(text)
-                    )),
+        }
+    }
+    // Roll all dice in the expression, keeping the result of each part
+    pub(crate) fn roll_log<R: Rng + ?Sized>(&self, opts: &RollOptions, rng: &mut R)
+            -> Result<DiceRoll, RollError> {
+        match self {
+            Self::Num(x) => Ok(DiceRoll::new(*x)),
+            Self::Var(name) => Err(DiceParseError::UnboundVariable(name.clone()).into()),
+            Self::Die(d) => d.roll_log(opts, rng),
+            Self::Neg(e) => {
+                return DiceRoll::neg(e.roll_log(opts, rng)?)
+                    .ok_or_else(|| RollError::Overflow(self.to_string()));
+            },
+            Self::Group(e) => Ok(DiceRoll::group(e.roll_log(opts, rng)?)),
+            Self::Binary(op, lhs, rhs) => {
+                let lhs = lhs.roll_log(opts, rng)?;
+                let rhs = rhs.roll_log(opts, rng)?;
+                let zero = rhs.value == 0 && op.divides();
+                return DiceRoll::binary(*op, lhs, rhs).ok_or_else(|| if zero {
+                    RollError::DivideByZero(self.to_string())
+                } else {
+                    RollError::Overflow(self.to_string())
+                });
+            },
+            Self::Call(func, args) => {
+                let mut rolls = vec![];
+                for arg in args {
+                    rolls.push(func.rounded(arg).roll_log(opts, rng)?);
                 }
+                return DiceRoll::call(*func, rolls)
+                    .ok_or_else(|| DiceParseError::InvalidArgs(self.to_string()).into());
+            },
+            Self::Check(op, lhs, target) => {
+                let lhs = lhs.roll_log(opts, rng)?;
+                let target = target.roll_log(opts, rng)?;
+                return Ok(DiceRoll::check(*op, lhs, target));
             },
         }
     }
 
-    pub fn roll(&self) -> DiceRoll {
+    /// Call a function on every set of dice in the expression, including nested sets
+    pub fn for_each_die_mut<F: FnMut(&mut Die)>(&mut self, f: &mut F) {
         match self {
-            Self::Mult(series) => {
-                let mut result = DiceRoll::new(1);
-                for d in series {
-                    match d {
-                        SignedDice::Pos(x) => result.mult(&x.roll()),
-                        SignedDice::Neg(x) => result.mult_neg(&x.roll()),
-                    }
+            Self::Num(_) | Self::Var(_) => (),
+            Self::Die(d) => {
+                d.number.for_each_die_mut(f);
+                d.sides.for_each_die_mut(f);
+                f(d);
+            },
+            Self::Neg(e) | Self::Group(e) => e.for_each_die_mut(f),
+            Self::Binary(_, lhs, rhs) | Self::Check(_, lhs, rhs) => {
+                lhs.for_each_die_mut(f);
+                rhs.for_each_die_mut(f);
+            },
+            Self::Call(_, args) => {
+                for arg in args {
+                    arg.for_each_die_mut(f);
                 }
-                return result;
             },
-            Self::Sum(series) => {
-                let mut result = DiceRoll::new(0);
-                for d in series {
-                    match d {
-                        SignedDice::Pos(x) => result.add(&x.roll()),
-                        SignedDice::Neg(x) => result.sub(&x.roll()),
-                    }
+        }
+    }
+    /// Copy of the expression for the damage of a critical hit, rolling extra damage for dice but
+    /// not numbers, or doubling the total
+    ///
+    /// Only the total compared by a check is changed, and dice rolled for the quantity or size of
+    /// other dice are left as they are.
+    pub fn crit(&self, mode: CritMode) -> Expr {
+        match (mode, self) {
+            (_, Self::Check(op, lhs, target)) => {
+                Self::Check(*op, Box::new(lhs.crit(mode)), target.clone())
+            },
+            (CritMode::DoubleTotal, _) => {
+                Self::Binary(BinOp::Mul, Box::new(Self::Num(2)), Box::new(self.clone()))
+            },
+            _ => {
+                let mut expr = self.clone();
+                expr.crit_dice_mut(mode);
+                return expr;
+            },
+        }
+    }
+    fn crit_dice_mut(&mut self, mode: CritMode) {
+        match self {
+            Self::Num(_) | Self::Var(_) => (),
+            Self::Die(d) => *self = d.crit(mode),
+            Self::Neg(e) | Self::Group(e) => e.crit_dice_mut(mode),
+            Self::Binary(_, lhs, rhs) | Self::Check(_, lhs, rhs) => {
+                lhs.crit_dice_mut(mode);
+                rhs.crit_dice_mut(mode);
+            },
+            Self::Call(_, args) => {
+                for arg in args {
+                    arg.crit_dice_mut(mode);
                 }
-                return result;
             },
-            Self::Die(d) => d.roll(),
-            Self::Const(x) => DiceRoll::new(*x),
         }
     }
+    // Copy of a division, inside any parentheses, rounding as another division operator does
+    fn with_rounding(&self, op: BinOp) -> Option<Expr> {
+        match self {
+            Self::Group(e) => Some(Self::Group(Box::new(e.with_rounding(op)?))),
+            Self::Binary(BinOp::Div | BinOp::DivUp | BinOp::DivRound, lhs, rhs) => {
+                Some(Self::Binary(op, lhs.clone(), rhs.clone()))
+            },
+            _ => None,
+        }
+    }
+
+    // How tightly the expression binds when displayed next to operators
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Num(n) if *n < 0 => NEG_PRECEDENCE,
+            Self::Neg(_) => NEG_PRECEDENCE,
+            Self::Binary(op, _, _) => op.precedence(),
+            Self::Check(_, _, _) => CHECK_PRECEDENCE,
+            _ if self.is_pool() => POOL_PRECEDENCE,
+            _ => TERM_PRECEDENCE,
+        }
+    }
+    // Whether the expression is a dice pool, which needs parentheses to be used in arithmetic
+    pub(crate) fn is_pool(&self) -> bool {
+        matches!(self, Self::Die(d) if d.pool().is_some())
+    }
+    // Whether the displayed expression starts with '-'
+    fn leads_with_neg(&self) -> bool {
+        match self {
+            Self::Num(n) => *n < 0,
+            Self::Neg(_) => true,
+            Self::Binary(_, lhs, _) => lhs.leads_with_neg(),
+            _ => false,
+        }
+    }
+    // Whether the displayed expression ends with a variable name, which would continue into any
+    // letters or numbers written after it
+    fn ends_with_var(&self) -> bool {
+        match self {
+            Self::Var(_) => true,
+            Self::Die(d) => d.modifiers.is_empty() && matches!(*d.sides, Expr::Var(_)),
+            Self::Neg(e) | Self::Binary(_, _, e) | Self::Check(_, _, e) => e.ends_with_var(),
+            _ => false,
+        }
+    }
+    // Display as an operand, adding parentheses if it would not parse back the same
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_prec: u8, signed: bool) -> fmt::Result {
+        if self.precedence() < min_prec || (!signed && self.leads_with_neg()) {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+impl FromStr for Expr {
+    type Err = DiceParseError;
+
+    fn from_str(s: &str) -> Result<Self, <Expr as FromStr>::Err> {
+        Parser::parse(s)
+    }
+}
+#[cfg(feature = "serde")]
+impl serde::Serialize for Expr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Expr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
 }
-impl fmt::Display for DiceSet {
+impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = String::from("");
-        let mut i: u8 = 0;
         match self {
-            Self::Sum(s) => {
-                for d in s {
-                    if i == 0 {
-                        match d {
-                            SignedDice::Pos(d) => result.push_str(&format!("{}", d)),
-                            SignedDice::Neg(d) => result.push_str(&format!("-{}", d)),
-                        }
-                    } else {
-                        match d {
-                            SignedDice::Pos(d) => result.push_str(&format!(" + {}", d)),
-                            SignedDice::Neg(d) => result.push_str(&format!(" - {}", d)),
-                        }
-                    }
-                    i += 1;
+            Self::Num(n) => write!(f, "{}", n),
+            Self::Var(v) => write!(f, "@{}", v),
+            Self::Die(d) => write!(f, "{}", d),
+            Self::Neg(e) => {
+                write!(f, "-")?;
+                e.fmt_operand(f, TERM_PRECEDENCE, false)
+            },
+            Self::Group(e) => write!(f, "({})", e),
+            Self::Binary(op, lhs, rhs) => {
+                lhs.fmt_operand(f, op.precedence(), true)?;
+                if *op == BinOp::Mul && lhs.ends_with_var() {
+                    write!(f, " ")?;
                 }
-                return write!(f, "{}", result);
+                write!(f, "{}", op)?;
+                rhs.fmt_operand(f, op.precedence() + 1, !matches!(op, BinOp::Add | BinOp::Sub))
             },
-            Self::Mult(s) => {
-                for d in s {
-                    if i == 0 {
-                        match d {
-                            SignedDice::Pos(d) => result.push_str(&format!("{}", d)),
-                            SignedDice::Neg(d) => result.push_str(&format!("-{}", d)),
-                        }
-                    } else {
-                        match d {
-                            SignedDice::Pos(d) => result.push_str(&format!("x{}", d)),
-                            SignedDice::Neg(d) => result.push_str(&format!("x-{}", d)),
-                        }
+            Self::Call(func, args) => {
+                write!(f, "{}(", func)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
                     }
-                    i += 1;
+                    write!(f, "{}", arg)?;
                 }
-                return write!(f, "{}", result);
+                write!(f, ")")
+            },
+            Self::Check(op, lhs, target) => {
+                lhs.fmt_operand(f, POOL_PRECEDENCE, true)?;
+                write!(f, " {} ", op)?;
+                target.fmt_operand(f, POOL_PRECEDENCE, true)
             },
-            Self::Die(d) => return write!(f, "{}", d),
-            Self::Const(n) => return write!(f, "{}", n),
         }
     }
 }
 
 
-// Dice Array
-type DiceSeries = Vec<SignedDice>;
+/// Binary operators in dice expressions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinOp {
+    /// Addition, written `+`
+    Add,
+    /// Subtraction, written `-`
+    Sub,
+    /// Multiplication, written `*` or `x`
+    Mul,
+    /// Division rounding down, written `/`
+    Div,
+    /// Division rounding up, written `/^`
+    DivUp,
+    /// Division rounding to the nearest number, with halves rounded up, written `/~`
+    DivRound,
+}
+impl BinOp {
+    /// How tightly the operator binds, higher precedence operators are applied first
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Add | Self::Sub => 2,
+            Self::Mul | Self::Div | Self::DivUp | Self::DivRound => 3,
+        }
+    }
+    /// Whether the operator is a division
+    pub fn divides(&self) -> bool {
+        matches!(self, Self::Div | Self::DivUp | Self::DivRound)
+    }
+    /// Apply the operator to two numbers, with no result for division by zero or a result too
+    /// large to fit
+    pub fn apply(&self, lhs: i64, rhs: i64) -> Option<i64> {
+        if rhs == 0 && self.divides() {
+            return None;
+        }
+        match self {
+            Self::Add => lhs.checked_add(rhs),
+            Self::Sub => lhs.checked_sub(rhs),
+            Self::Mul => lhs.checked_mul(rhs),
+            // Divisions are calculated with wider numbers, only their results can be too large
+            _ => {
+                let (lhs, rhs) = (i128::from(lhs), i128::from(rhs));
+                let result = match self {
+                    Self::Div => Integer::div_floor(&lhs, &rhs),
+                    Self::DivUp => Integer::div_ceil(&lhs, &rhs),
+                    _ => {
+                        // Halves round up, found by rounding down half the divisor past the result
+                        let (lhs, rhs) = if rhs < 0 { (-lhs, -rhs) } else { (lhs, rhs) };
+                        Integer::div_floor(&(2 * lhs + rhs), &(2 * rhs))
+                    },
+                };
+                return i64::try_from(result).ok();
+            },
+        }
+    }
+}
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add => write!(f, " + "),
+            Self::Sub => write!(f, " - "),
+            Self::Mul => write!(f, "x"),
+            Self::Div => write!(f, "/"),
+            Self::DivUp => write!(f, "/^"),
+            Self::DivRound => write!(f, "/~"),
+        }
+    }
+}
 
 
-// Signed Dice Set
-#[derive(Debug)]
-pub enum SignedDice {
-    Pos(DiceSet),
-    Neg(DiceSet),
+/// Functions which can be called in dice expressions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Func {
+    /// Lowest of two or more arguments, written `min`
+    Min,
+    /// Highest of two or more arguments, written `max`
+    Max,
+    /// Absolute value of one argument, written `abs`
+    Abs,
+    /// One argument, with a division in it rounded down, written `floor`
+    Floor,
+    /// One argument, with a division in it rounded up, written `ceil`
+    Ceil,
+    /// First argument limited to between the second and third, written `clamp`
+    Clamp,
 }
-impl SignedDice {
-    fn parse_pos(text: &str) -> Result<SignedDice, DiceParseError> {
-        Ok(SignedDice::Pos(DiceSet::parse(text)?))
+impl Func {
+    /// Function with a name, if there is one
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "abs" => Some(Self::Abs),
+            "floor" => Some(Self::Floor),
+            "ceil" => Some(Self::Ceil),
+            "clamp" => Some(Self::Clamp),
+            _ => None,
+        }
     }
-    fn parse_neg(text: &str) -> Result<SignedDice, DiceParseError> {
-        Ok(SignedDice::Neg(DiceSet::parse(text)?))
+    /// Name the function is written with
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Abs => "abs",
+            Self::Floor => "floor",
+            Self::Ceil => "ceil",
+            Self::Clamp => "clamp",
+        }
     }
+    /// Whether the function can be called with a number of arguments
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Self::Min | Self::Max => count >= 2,
+            Self::Clamp => count == 3,
+            Self::Abs | Self::Floor | Self::Ceil => count == 1,
+        }
+    }
+    /// Apply the function to numbers, with no result for the wrong number of arguments
+    ///
+    /// Clamping to a range with its lower limit above its upper limit gives the upper limit.
+    pub fn apply(&self, args: &[i64]) -> Option<i64> {
+        if !self.accepts(args.len()) {
+            return None;
+        }
+        match self {
+            Self::Min => args.iter().copied().min(),
+            Self::Max => args.iter().copied().max(),
+            Self::Abs => Some(args[0].saturating_abs()),
+            Self::Floor | Self::Ceil => Some(args[0]),
+            Self::Clamp => Some(args[0].max(args[1]).min(args[2])),
+        }
+    }
+    // Argument as it is calculated, with floor and ceil rounding a division directly in them
+    pub(crate) fn rounded<'a>(&self, arg: &'a Expr) -> Cow<'a, Expr> {
+        let op = match self {
+            Self::Floor => BinOp::Div,
+            Self::Ceil => BinOp::DivUp,
+            _ => return Cow::Borrowed(arg),
+        };
+        match arg.with_rounding(op) {
+            Some(e) => Cow::Owned(e),
+            None => Cow::Borrowed(arg),
+        }
+    }
+}
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+/// Ways of rolling the damage of a critical hit
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CritMode {
+    /// Roll twice as many of each set of dice, so `2d6 + 4` becomes `4d6 + 4`
+    #[default]
+    DoubleDice,
+    /// Add the highest roll of each set of dice to rolling it, so `2d6 + 4` becomes
+    /// `12 + 2d6 + 4`
+    MaxPlusRoll,
+    /// Double the total, so `2d6 + 4` becomes `2x(2d6 + 4)`
+    DoubleTotal,
 }
 
 
-// Dice with one size
-#[derive(Debug)]
+/// A set of dice with one size
+///
+/// The quantity and size are usually numbers, but may be groups which are rolled first.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Die {
-    number: u32,
-    sides: u32
+    /// Quantity of dice rolled
+    pub number: Box<Expr>,
+    /// Number of sides on each die
+    pub sides: Box<Expr>,
+    /// Modifiers applied in order to the dice rolled
+    pub modifiers: Vec<Modifier>,
 }
 impl Die {
     pub fn new(n: u32, s: u32) -> Die {
         Die {
-            number: n,
-            sides: s,
+            number: Box::new(Expr::Num(n.into())),
+            sides: Box::new(Expr::Num(s.into())),
+            modifiers: vec![],
+        }
+    }
+    /// Add a modifier to the dice
+    pub fn with_modifier(mut self, m: Modifier) -> Die {
+        self.modifiers.push(m);
+        return self;
+    }
+    /// Roll the dice with a random number generator and calculate their result
+    pub fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<i64, RollError> {
+        Ok(self.roll_log(&RollOptions::default(), rng)?.value)
+    }
+    // Roll the dice, keeping the result of each die
+    pub(crate) fn roll_log<R: Rng + ?Sized>(&self, opts: &RollOptions, rng: &mut R)
+            -> Result<DiceRoll, RollError> {
+        let number = self.number.roll_log(opts, rng)?;
+        let sides = self.sides.roll_log(opts, rng)?;
+        // Negative quantities roll no dice, dice without sides always roll 0
+        let count = number.value.max(0);
+        let size = u32::try_from(sides.value.max(0))
+            .map_err(|_| RollError::TooManyDice(self.to_string()))?;
+        if count > i64::from(DICE_LIMIT) {
+            return Err(RollError::TooManyDice(self.to_string()));
+        }
+        let mut log = self.roll_set(count, size, opts, rng);
+        // Pools count successes, other dice sum the values kept
+        let total = |log: &RollLog| match log.successes() {
+            Some(n) => Ok(n),
+            None => log.kept().try_fold(0i64, |sum, v| sum.checked_add(i64::try_from(v).ok()?))
+                .ok_or_else(|| RollError::Overflow(self.to_string())),
+        };
+        // With advantage or disadvantage the set is rolled again, and the worse set dropped
+        let advantage = self.modifiers.iter().find(|m| m.is_advantage());
+        if let Some(m) = advantage {
+            let mut other = self.roll_set(count, size, opts, rng);
+            let first = total(&log)?;
+            let second = total(&other)?;
+            let use_second = match m {
+                Modifier::Advantage => second > first,
+                _ => second < first,
+            };
+            if use_second {
+                log.drop_all();
+            } else {
+                other.drop_all();
+            }
+            log.append(&other);
+        }
+        return Ok(DiceRoll::set(number, sides, total(&log)?, &log, opts.crit_range));
+    }
+
+    // Dice rolled on a critical hit in place of these dice
+    fn crit(&self, mode: CritMode) -> Expr {
+        match mode {
+            CritMode::DoubleDice => {
+                let number = match *self.number {
+                    Expr::Num(n) => Expr::Num(n.saturating_mul(2)),
+                    _ => Expr::Group(Box::new(Expr::Binary(BinOp::Mul, Box::new(Expr::Num(2)),
+                        self.number.clone()))),
+                };
+                Expr::Die(Die { number: Box::new(number), ..self.clone() })
+            },
+            CritMode::MaxPlusRoll => Expr::Binary(BinOp::Add, Box::new(self.highest()),
+                Box::new(Expr::Die(self.clone()))),
+            CritMode::DoubleTotal => {
+                Expr::Binary(BinOp::Mul, Box::new(Expr::Num(2)), Box::new(Expr::Die(self.clone())))
+            },
         }
     }
-    pub fn roll(&self) -> DiceRoll {
-        let mut sum = 0;
-        let mut log = RollLog::new(self.sides);
-        for _ in 0..self.number {
-            let range: i64 = self.sides.into();
-            let r: i64 = rand::thread_rng().gen_range(1..range + 1);
-            sum += r;
-            log.log(r.try_into().unwrap());
+    // Highest result of rolling the dice once without exploding, counting only the dice kept when
+    // the quantity and size are numbers
+    fn highest(&self) -> Expr {
+        let (number, sides) = match (&*self.number, &*self.sides) {
+            (Expr::Num(n), Expr::Num(s)) => ((*n).max(0), (*s).max(0)),
+            _ => return Expr::Binary(BinOp::Mul, self.number.clone(), self.sides.clone()),
+        };
+        let mut kept = number;
+        for m in &self.modifiers {
+            kept = match *m {
+                Modifier::KeepHighest(k) | Modifier::KeepLowest(k) => kept.min(k.into()),
+                Modifier::DropHighest(k) | Modifier::DropLowest(k) => (kept - i64::from(k)).max(0),
+                _ => kept,
+            };
+        }
+        // Pools count each kept die as at most one success
+        if self.pool().is_some() {
+            return Expr::Num(kept);
         }
-        let result = DiceRoll::new_roll(sum, log);
-        return result;
+        return Expr::Num(kept.saturating_mul(sides));
     }
 
+    // Roll the dice once, rerolling and exploding them, then applying keep and drop modifiers and
+    // counting successes
+    fn roll_set<R: Rng + ?Sized>(&self, count: i64, size: u32, opts: &RollOptions, rng: &mut R)
+            -> RollLog {
+        let mut log = RollLog::new(size);
+        let explode = self.explode();
+        let reroll = self.reroll(opts);
+        // Extra rolls left before no more dice explode or are rerolled
+        let mut extra = EXTRA_ROLL_LIMIT;
+        for _ in 0..count {
+            if size == 0 {
+                log.log(0);
+                continue;
+            }
+            let mut first = log.len();
+            let mut r = rng.gen_range(1..=size);
+            log.log(r);
+            // Only the first roll of each die is rerolled, not extra rolls from exploding
+            if let Some((depth, target)) = reroll {
+                for _ in 0..depth {
+                    if extra == 0 || !target.matches(r.into()) {
+                        break;
+                    }
+                    extra -= 1;
+                    r = rng.gen_range(1..=size);
+                    log.log_linked(r, Link::Reroll(first));
+                    first = log.len() - 1;
+                }
+            }
+            if let Some((compound, target)) = explode {
+                // Explode on the highest roll unless given a target
+                let target = target.unwrap_or(Compare::new(CmpOp::Eq, size.into()));
+                let mut cause = first;
+                for _ in 0..opts.explode_depth {
+                    if extra == 0 || !target.matches(r.into()) {
+                        break;
+                    }
+                    extra -= 1;
+                    r = rng.gen_range(1..=size);
+                    if compound {
+                        log.log_linked(r, Link::Compound(first));
+                    } else {
+                        log.log_linked(r, Link::Explode(cause));
+                        cause = log.len() - 1;
+                    }
+                }
+            }
+        }
+        for m in &self.modifiers {
+            m.apply(&mut log);
+        }
+        if let Some((success, failure)) = self.pool() {
+            log.count(success, failure);
+        }
+        return log;
+    }
+
+    // Whether the dice compound and their target, if they explode
+    pub(crate) fn explode(&self) -> Option<(bool, Option<Compare>)> {
+        self.modifiers.iter().find_map(|m| match m {
+            Modifier::Explode(c) => Some((false, *c)),
+            Modifier::Compound(c) => Some((true, *c)),
+            _ => None,
+        })
+    }
+    // Most times each die is rerolled and their target, if they are rerolled
+    pub(crate) fn reroll(&self, opts: &RollOptions) -> Option<(u32, Compare)> {
+        self.modifiers.iter().find_map(|m| match m {
+            Modifier::RerollOnce(c) => Some((1, *c)),
+            Modifier::RerollRecursive(c) => Some((opts.reroll_depth, *c)),
+            _ => None,
+        })
+    }
+    // Success and failure targets, if the dice are counted as a pool
+    pub(crate) fn pool(&self) -> Option<(Compare, Option<Compare>)> {
+        let success = self.modifiers.iter().find_map(|m| match m {
+            Modifier::Success(c) => Some(*c),
+            _ => None,
+        })?;
+        let failure = self.modifiers.iter().find_map(|m| match m {
+            Modifier::Failure(c) => Some(*c),
+            _ => None,
+        });
+        return Some((success, failure));
+    }
+
+    // Display quantity or size, only plain numbers, variables, groups and function calls are
+    // written without parentheses
+    fn fmt_part(part: &Expr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match part {
+            Expr::Num(n) if *n >= 0 => write!(f, "{}", n),
+            Expr::Var(_) | Expr::Group(_) | Expr::Call(_, _) => write!(f, "{}", part),
+            _ => write!(f, "({})", part),
+        }
+    }
 }
 impl fmt::Display for Die {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}d{}", self.number, self.sides)
+        Die::fmt_part(&self.number, f)?;
+        // Variable names are separated from the letters following them
+        if matches!(*self.number, Expr::Var(_)) {
+            write!(f, " ")?;
+        }
+        write!(f, "d")?;
+        Die::fmt_part(&self.sides, f)?;
+        // Pool targets are written first, so they are not read as the target of an exploding die,
+        // and a variable size is separated from a modifier starting with a letter
+        let modifiers = self.modifiers.iter().filter(|m| m.is_pool())
+            .chain(self.modifiers.iter().filter(|m| !m.is_pool()));
+        for (i, m) in modifiers.enumerate() {
+            let text = m.to_string();
+            if i == 0 && matches!(*self.sides, Expr::Var(_))
+                    && text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", text)?;
+        }
+        return Ok(());
     }
 }
 
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // DiceSet (whitebox)
-    #[test]
-    fn test_dice_set_new() {
-        let x = match DiceSet::new() {
-            DiceSet::Const(n) => n,
-            _ => panic!(),
+/// Modifiers changing how the dice in a set are rolled and counted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Modifier {
+    /// Keep only the highest dice, written `kh#`
+    KeepHighest(u32),
+    /// Keep only the lowest dice, written `kl#`
+    KeepLowest(u32),
+    /// Drop the highest dice, written `dh#`
+    DropHighest(u32),
+    /// Drop the lowest dice, written `dl#`
+    DropLowest(u32),
+    /// Roll the set twice and use the higher result, written `adv`
+    Advantage,
+    /// Roll the set twice and use the lower result, written `dis`
+    Disadvantage,
+    /// Roll another die for each die on the highest number or matching a target, written `!`
+    /// or `!>#`
+    Explode(Option<Compare>),
+    /// Explode dice adding extra rolls to the die which exploded, written `!!` or `!!>#`
+    Compound(Option<Compare>),
+    /// Reroll dice matching a target once, written `r#`, `r<#`, or `ro#`
+    RerollOnce(Compare),
+    /// Reroll dice until they do not match a target, written `rr#` or `rr<#`
+    RerollRecursive(Compare),
+    /// Count dice matching a target as successes instead of summing them, written `>#`
+    Success(Compare),
+    /// Count dice matching a target as failures subtracting from the successes, written `f#` or
+    /// `f<#`
+    Failure(Compare),
+}
+impl Modifier {
+    /// Whether the modifier is advantage or disadvantage
+    pub fn is_advantage(&self) -> bool {
+        matches!(self, Self::Advantage | Self::Disadvantage)
+    }
+    /// Whether the modifier keeps or drops dice
+    pub fn is_keep(&self) -> bool {
+        matches!(self, Self::KeepHighest(_) | Self::KeepLowest(_) | Self::DropHighest(_)
+            | Self::DropLowest(_))
+    }
+    /// Whether the modifier is a success or failure target of a dice pool
+    pub fn is_pool(&self) -> bool {
+        matches!(self, Self::Success(_) | Self::Failure(_))
+    }
+    // Change which rolls in the log are kept
+    fn apply(&self, log: &mut RollLog) {
+        // Kept dice from lowest to highest, ties ordered by when they were rolled
+        let mut order: Vec<usize> = log.dice().filter(|i| !log.is_dropped(*i)).collect();
+        order.sort_by_key(|i| log.value(*i));
+        let kept = order.len();
+        let (lowest, n) = match *self {
+            Self::KeepHighest(n) => (true, kept.saturating_sub(n as usize)),
+            Self::KeepLowest(n) => (false, kept.saturating_sub(n as usize)),
+            Self::DropHighest(n) => (false, (n as usize).min(kept)),
+            Self::DropLowest(n) => (true, (n as usize).min(kept)),
+            _ => return,
         };
-        assert!(x == 0);
+        if !lowest {
+            order.reverse();
+        }
+        for i in &order[..n] {
+            log.drop(*i);
+        }
     }
-    #[test]
-    fn test_dice_set_parse() {
-        let ds = DiceSet::parse("-1d8+2*-4-3").unwrap();
-        match ds {
-            DiceSet::Sum(s) => {
-                // -1d8
-                match &s[0] {
-                    SignedDice::Neg(x) => match x {
-                        DiceSet::Die(d) => {
-                            if format!("{}", d) != "1d8" {
-                                panic!();
-                            }
-                        },
-                        _ => panic!(),
-                    },
-                    _ => panic!(),
+}
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeepHighest(n) => write!(f, "kh{}", n),
+            Self::KeepLowest(n) => write!(f, "kl{}", n),
+            Self::DropHighest(n) => write!(f, "dh{}", n),
+            Self::DropLowest(n) => write!(f, "dl{}", n),
+            Self::Advantage => write!(f, "adv"),
+            Self::Disadvantage => write!(f, "dis"),
+            Self::Explode(c) | Self::Compound(c) => {
+                if let Self::Compound(_) = self {
+                    write!(f, "!!")?;
+                } else {
+                    write!(f, "!")?;
                 }
-                // 2*-4
-                match &s[1] {
-                    SignedDice::Pos(x) => match x {
-                        DiceSet::Mult(arr) => {
-                            match &arr[0] {
-                                SignedDice::Pos(n) => match n {
-                                    DiceSet::Const(i) => assert!(*i == 2),
-                                    _ => panic!(),
-                                },
-                                _ => panic!(),
-                            }
-                            match &arr[1] {
-                                SignedDice::Neg(n) => match n {
-                                    DiceSet::Const(i) => assert!(*i == 4),
-                                    _ => panic!(),
-                                },
-                                _ => panic!(),
-                            }
-                        },
-                        _ => panic!(),
-                    }
-                    _ => panic!(),
+                match c {
+                    Some(c) => write!(f, "{}", c),
+                    None => Ok(()),
                 }
-                // -3
-                match &s[2] {
-                    SignedDice::Neg(x) => match x {
-                        DiceSet::Const(i) => assert!(*i == 3),
-                        _ => panic!(),
-                    },
-                    _ => panic!(),
+            },
+            Self::RerollOnce(c) | Self::RerollRecursive(c) | Self::Failure(c) => {
+                match self {
+                    Self::RerollOnce(_) => write!(f, "r")?,
+                    Self::RerollRecursive(_) => write!(f, "rr")?,
+                    _ => write!(f, "f")?,
+                }
+                // Equality is written as just the number
+                match c.op {
+                    CmpOp::Eq => write!(f, "{}", c.value),
+                    _ => write!(f, "{}", c),
                 }
             },
-            _ => panic!(),
+            Self::Success(c) => write!(f, "{}", c),
         }
     }
+}
+
+
+/// Comparison operators
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CmpOp {
+    /// Less than, written `<`
+    Lt,
+    /// Less than or equal to, written `<=`
+    Le,
+    /// Greater than, written `>`
+    Gt,
+    /// Greater than or equal to, written `>=`
+    Ge,
+    /// Equal to, written `=`
+    Eq,
+}
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lt => write!(f, "<"),
+            Self::Le => write!(f, "<="),
+            Self::Gt => write!(f, ">"),
+            Self::Ge => write!(f, ">="),
+            Self::Eq => write!(f, "="),
+        }
+    }
+}
+
+
+/// A target which rolls are compared against, such as `>=5`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Compare {
+    pub op: CmpOp,
+    pub value: i64,
+}
+impl Compare {
+    pub fn new(op: CmpOp, value: i64) -> Self {
+        Self { op, value }
+    }
+    /// Whether a number meets the target
+    pub fn matches(&self, n: i64) -> bool {
+        match self.op {
+            CmpOp::Lt => n < self.value,
+            CmpOp::Le => n <= self.value,
+            CmpOp::Gt => n > self.value,
+            CmpOp::Ge => n >= self.value,
+            CmpOp::Eq => n == self.value,
+        }
+    }
+}
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{thread_rng, SeedableRng};
+
+    fn num(n: i64) -> Box<Expr> {
+        Box::new(Expr::Num(n))
+    }
+
+    // Expr
+    #[test]
+    fn test_expr_parse() {
+        let e: Expr = "-1d8+2*-4-3".parse().unwrap();
+        let expected = Expr::Binary(BinOp::Sub,
+            Box::new(Expr::Binary(BinOp::Add,
+                Box::new(Expr::Neg(Box::new(Expr::Die(Die::new(1, 8))))),
+                Box::new(Expr::Binary(BinOp::Mul, num(2), Box::new(Expr::Neg(num(4))))))),
+            num(3));
+        assert_eq!(e, expected);
+    }
     #[test]
-    fn test_dice_set_parse_err() {
-        let ds = DiceSet::parse("1f4+ 2");
-        assert!(match ds {
+    fn test_expr_parse_err() {
+        let e = "1g4+ 2".parse::<Expr>();
+        assert!(match e {
             Ok(_) => false,
             Err(e) => match e {
-                DiceParseError::InvalidChar(c) => c == 'f',
+                DiceParseError::InvalidChar(c) => c == 'g',
                 _ => false,
             },
         });
     }
     #[test]
-    fn test_dice_set_roll() {
-        let ds = DiceSet::parse("5d6 - 1d8 + 7").unwrap();
+    fn test_expr_roll() {
+        let e: Expr = "5d6 - 1d8 + 7".parse().unwrap();
         let mut sum: i64 = 0;
         for _ in 1..100 {
-            sum += ds.roll().num();
+            sum += e.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value;
         }
         assert!(sum <= 36*100);
         assert!(sum >= 4*100);
     }
     #[test]
-    fn test_dice_set_fmt() {
-        let ds = DiceSet::parse("5*3d4 + 1d12").unwrap();
-        assert!(format!("{}", ds) == "5x3d4 + 1d12")
+    fn test_expr_roll_with() {
+        let e: Expr = "4d6kh3 + 1d20!".parse().unwrap();
+        let mut a = StdRng::seed_from_u64(17);
+        let mut b = StdRng::seed_from_u64(17);
+        for _ in 1..20 {
+            assert_eq!(e.roll_with(&mut a).unwrap(), e.roll_with(&mut b).unwrap());
+        }
     }
-
-    // SignedDice
     #[test]
-    fn test_signed_dice_pos() {
-        let sd = SignedDice::parse_pos("4").unwrap();
-        assert!(match sd {
-            SignedDice::Pos(p) => match p {
-                DiceSet::Const(x) => x == 4,
-                _ => false,
-            },
-            SignedDice::Neg(_) => false,
+    fn test_expr_roll_group() {
+        let e: Expr = "2d(1d4+2)".parse().unwrap();
+        for _ in 1..100 {
+            let r = e.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value;
+            assert!(r >= 2);
+            assert!(r <= 12);
+        }
+        let e: Expr = "-(2 - 5)x(1 + 1)".parse().unwrap();
+        assert_eq!(e.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value, 6);
+    }
+    #[test]
+    fn test_expr_fmt() {
+        let e: Expr = "5*3d4 + 1d12".parse().unwrap();
+        assert!(format!("{}", e) == "5x3d4 + 1d12");
+        let e: Expr = "( (1d6+ 2)* 2) -(3)d( 1d4 )".parse().unwrap();
+        assert!(format!("{}", e) == "((1d6 + 2)x2) - (3)d(1d4)");
+    }
+    #[test]
+    fn test_expr_fmt_built() {
+        // (1 + 2)x3
+        let e = Expr::Binary(BinOp::Mul, Box::new(Expr::Binary(BinOp::Add, num(1), num(2))),
+            num(3));
+        assert_eq!(format!("{}", e), "(1 + 2)x3");
+        // 4 - (1 - 2)
+        let e = Expr::Binary(BinOp::Sub, num(4), Box::new(Expr::Binary(BinOp::Sub, num(1),
+            num(2))));
+        assert_eq!(format!("{}", e), "4 - (1 - 2)");
+        // 4 + (-1d6)
+        let e = Expr::Binary(BinOp::Add, num(4), Box::new(Expr::Neg(Box::new(
+            Expr::Die(Die::new(1, 6))))));
+        assert_eq!(format!("{}", e), "4 + (-1d6)");
+        // (1 + 1)d(-2)
+        let e = Expr::Die(Die {
+            number: Box::new(Expr::Binary(BinOp::Add, num(1), num(1))),
+            sides: num(-2),
+            modifiers: vec![],
         });
+        assert_eq!(format!("{}", e), "(1 + 1)d(-2)");
     }
     #[test]
-    fn test_signed_dice_neg() {
-        let sd = SignedDice::parse_neg("7d8").unwrap();
-        println!("{:?}", sd);
-        assert!(match sd {
-            SignedDice::Pos(_) => false,
-            SignedDice::Neg(p) => match p {
-                DiceSet::Die(d) => "7d8" == &format!("{}", d),
-                _ => false,
-            },
+    fn test_expr_fmt_round_trip() {
+        for text in ["-1d4x2", "8x6d12 - 4 + 3d6", "2x-(1d4 + 1)", "((1))d(2d(3))",
+                "4d6kh3dl1 + 2d(4)kl1", "1d20adv + 1d20dis", "2d6r2 + 1d8rr<=2!", "10d6r<7",
+                "(10d10>=8) - (6d6>4f<=2!)", "1d20 + @str_mod - @prof", "@level d@sides", "(@n)d6",
+                "@a x@b - 1d@s kh1 - @n x3", "-@n x2", "1d@s x2", "1d20/2 + 3x4/^3 - 5/~-2",
+                "9/(3/2)", "2x(4/2)", "(2x4)/^2x1d6", "max(1, 1d4 - 1)", "min(5, floor(@level/2))",
+                "clamp(2d6, 3, 10)x2", "ceil((1d8 + 1)/2)", "max(1, 2)d6", "1dmin(@a, 6)kh1",
+                "-abs(1d4 - 1d4)", "@a xmin(@b, 1)", "1d20 + 5 >= 15", "1d20 < @dc", "2d6>4 >= 2",
+                "1d6! > 4", "1d@s>4 >= 1", "-1d4 = -2"] {
+            let e: Expr = text.parse().unwrap();
+            assert_eq!(format!("{}", e), text);
+            assert_eq!(format!("{}", e).parse::<Expr>().unwrap(), e);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_expr_serde() {
+        let e: Expr = "(1d4 + 2)x2 - 4d6kh3!".parse().unwrap();
+        let json = serde_json::to_string(&e).unwrap();
+        assert_eq!(json, "\"(1d4 + 2)x2 - 4d6kh3!\"");
+        assert_eq!(serde_json::from_str::<Expr>(&json).unwrap(), e);
+        assert!(serde_json::from_str::<Expr>("\"1d\"").is_err());
+    }
+    #[test]
+    fn test_expr_bind() {
+        let e: Expr = "1d20 + @str + @prof x(@level)d6 + @str".parse().unwrap();
+        assert_eq!(e.variables(), vec!["str", "prof", "level"]);
+        let context = HashMap::from([(String::from("str"), -1), (String::from("prof"), 2),
+            (String::from("level"), 3), (String::from("unused"), 9)]);
+        let bound = e.bind(&context).unwrap();
+        assert_eq!(format!("{}", bound), "1d20 + (-1) + 2x(3)d6 + (-1)");
+        assert!(bound.variables().is_empty());
+        assert!(matches!(e.bind(&HashMap::from([(String::from("str"), 1)])),
+            Err(DiceParseError::UnboundVariable(s)) if s == "prof"));
+        assert_eq!(Expr::Num(4).bind(&HashMap::new()).unwrap(), Expr::Num(4));
+    }
+    #[test]
+    fn test_expr_roll_err() {
+        let e: Expr = "1d4 + @x".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::Parse(DiceParseError::UnboundVariable(String::from("x")))));
+        let e: Expr = "2 + 1d4/(1d1 - 1)".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::DivideByZero(String::from("1d4/(1d1 - 1)"))));
+        let e: Expr = "(1/0)d6".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::DivideByZero(String::from("1/0"))));
+        let e: Expr = "(1d1x100000000)d6".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::TooManyDice(String::from("(1d1x100000000)d6"))));
+        let e: Expr = "1d(1d1x9999999999)".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::TooManyDice(String::from("1d(1d1x9999999999)"))));
+        let e: Expr = "1d4294967295 + 100000d1".parse().unwrap();
+        assert!(e.roll_with(&mut thread_rng()).is_ok());
+        let e: Expr = "1 + 9999999999x9999999999".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::Overflow(String::from("9999999999x9999999999"))));
+        let e: Expr = "-(-9223372036854775807 - 1)".parse().unwrap();
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::Overflow(String::from("-(-9223372036854775807 - 1)"))));
+    }
+    #[test]
+    fn test_expr_roll_div() {
+        for (text, result) in [("7/2", 3), ("-7/2", -4), ("7/-2", -4), ("7/^2", 4), ("-7/^2", -3),
+                ("7/~2", 4), ("-7/~2", -3), ("8/~3", 3), ("7/~3", 2), ("-8/~3", -3), ("7/~-2", -3),
+                ("3d1/2", 1), ("1 + 9/2x2", 9), ("9/(2x2)", 2), ("18/3/2", 3),
+                ("9223372036854775807/~2", 4611686018427387904)] {
+            let e: Expr = text.parse().unwrap();
+            assert_eq!(e.roll_with(&mut thread_rng()), Ok(result), "{}", text);
+        }
+    }
+    #[test]
+    fn test_expr_roll_func() {
+        for (text, expected) in [("max(1, 1d1 - 1)", 1), ("min(5, floor(7/2))", 3),
+                ("ceil(7/2)", 4), ("ceil((7/~2))", 4), ("floor(7/~2)", 3), ("ceil(7/2 + 0)", 3),
+                ("abs(-3)", 3), ("abs(2 - 1d1x5)", 3), ("max(1, 4, 2) + min(4, 2, 3)", 6),
+                ("clamp(12, 1, 10)", 10), ("clamp(-3, 1, 10)", 1), ("clamp(5, 1, 10)", 5),
+                ("clamp(5, 8, 2)", 2), ("max(1, 2)d1", 2), ("3dmin(1, 9)", 3)] {
+            let e: Expr = text.parse().unwrap();
+            assert_eq!(e.roll_with(&mut thread_rng()), Ok(expected), "{}", text);
+        }
+        let e = Expr::Call(Func::Clamp, vec![Expr::Num(1)]);
+        assert_eq!(e.roll_with(&mut thread_rng()),
+            Err(RollError::Parse(DiceParseError::InvalidArgs(String::from("clamp(1)")))));
+        let context = HashMap::from([(String::from("level"), 13)]);
+        let e: Expr = "min(5, ceil(@level/2))".parse().unwrap();
+        assert_eq!(e.bind(&context).unwrap().roll_with(&mut thread_rng()), Ok(5));
+        assert_eq!(e.variables(), vec!["level"]);
+    }
+    #[test]
+    fn test_expr_roll_check() {
+        let e: Expr = "10d1 + 2 >= 1d1 + 11".parse().unwrap();
+        let r = e.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+        assert_eq!((r.value, r.target(), r.passed()), (12, Some(12), Some(true)));
+        assert_eq!(r.sets().len(), 2);
+        let e: Expr = "(3d1) < 3".parse().unwrap();
+        let r = e.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+        assert_eq!((r.value, r.target(), r.passed()), (3, Some(3), Some(false)));
+        let e: Expr = "1d20 >= @dc".parse().unwrap();
+        assert_eq!(e.variables(), vec!["dc"]);
+    }
+    #[test]
+    fn test_expr_crit() {
+        let crit = |text: &str, mode| format!("{}", text.parse::<Expr>().unwrap().crit(mode));
+        assert_eq!(crit("2d6 + 4", CritMode::DoubleDice), "4d6 + 4");
+        assert_eq!(crit("2d6 + 4", CritMode::MaxPlusRoll), "12 + 2d6 + 4");
+        assert_eq!(crit("2d6 + 4", CritMode::DoubleTotal), "2x(2d6 + 4)");
+        assert_eq!(crit("1d8 + 1d6 - 1", CritMode::DoubleDice), "2d8 + 2d6 - 1");
+        assert_eq!(crit("@n d6 + @mod", CritMode::DoubleDice), "(2x@n)d6 + @mod");
+        assert_eq!(crit("(1d4)d6", CritMode::DoubleDice), "(2x(1d4))d6");
+        assert_eq!(crit("-1d6x2", CritMode::MaxPlusRoll), "-(6 + 1d6)x2");
+        assert_eq!(crit("4d6kh3 + (6d6>4)", CritMode::MaxPlusRoll),
+            "18 + 4d6kh3 + (6 + (6d6>4))");
+        assert_eq!(crit("6d6>4", CritMode::DoubleTotal), "2x(6d6>4)");
+        assert_eq!(crit("1d@s + 2", CritMode::MaxPlusRoll), "1x@s + 1d@s + 2");
+        assert_eq!(crit("max(1, 1d4 - 1)", CritMode::DoubleDice), "max(1, 2d4 - 1)");
+        assert_eq!(crit("1d20 + 5 >= 1d4", CritMode::DoubleTotal), "2x(1d20 + 5) >= 1d4");
+        assert_eq!(crit("7", CritMode::DoubleDice), "7");
+    }
+    #[test]
+    fn test_func_apply() {
+        assert_eq!(Func::Min.apply(&[3, -1, 2]), Some(-1));
+        assert_eq!(Func::Max.apply(&[3, -1, 2]), Some(3));
+        assert_eq!(Func::Abs.apply(&[i64::MIN]), Some(i64::MAX));
+        assert_eq!(Func::Min.apply(&[3]), None);
+        assert_eq!(Func::Floor.apply(&[3, 4]), None);
+        for func in [Func::Min, Func::Max, Func::Abs, Func::Floor, Func::Ceil, Func::Clamp] {
+            assert_eq!(Func::from_name(func.name()), Some(func));
+        }
+        assert_eq!(Func::from_name("round"), None);
+    }
+    #[test]
+    fn test_expr_for_each_die_mut() {
+        let mut e: Expr = "1d20 + (1d4)d6 - 2".parse().unwrap();
+        let mut sides = vec![];
+        e.for_each_die_mut(&mut |d| {
+            sides.push(format!("{}", d.sides));
+            d.modifiers.push(Modifier::KeepHighest(1));
         });
+        assert_eq!(sides, vec!["20", "4", "6"]);
+        assert_eq!(format!("{}", e), "1d20kh1 + (1d4kh1)d6kh1 - 2");
     }
 
     // Die
     #[test]
     fn test_die_new() {
         let d = Die::new(1, 6);
-        assert_eq!(d.number, 1);
-        assert_eq!(d.sides, 6);
+        assert!(matches!(*d.number, Expr::Num(1)));
+        assert!(matches!(*d.sides, Expr::Num(6)));
     }
     #[test]
     fn test_die_roll() {
         let d = Die::new(2, 10);
         let mut sum: i64 = 0;
         for _ in 1..100 {
-            sum += d.roll().num();
+            sum += d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value;
         }
         assert!(sum <= 20*100);
         assert!(sum >= 2*100);
     }
     #[test]
+    fn test_die_roll_with() {
+        let d = Die::new(3, 8);
+        let mut a = StdRng::seed_from_u64(5);
+        let mut b = StdRng::seed_from_u64(5);
+        let first: Vec<i64> = (0..10).map(|_| d.roll_with(&mut a).unwrap()).collect();
+        let second: Vec<i64> = (0..10).map(|_| d.roll_with(&mut b).unwrap()).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|r| (3..=24).contains(r)));
+    }
+    #[test]
+    fn test_die_roll_keep() {
+        let d = Die::new(4, 6).with_modifier(Modifier::KeepHighest(3));
+        for _ in 1..100 {
+            let r = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value;
+            assert!(r >= 3);
+            assert!(r <= 18);
+        }
+        let d = Die::new(3, 20).with_modifier(Modifier::DropHighest(1))
+            .with_modifier(Modifier::DropLowest(5));
+        assert_eq!(d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value, 0);
+    }
+    #[test]
+    fn test_die_roll_advantage() {
+        // Rolls as written in the log, dropped rolls are negative
+        fn logged(r: &DiceRoll) -> Vec<i64> {
+            r.full_log().split_whitespace().skip(1).map(|w| match w.strip_prefix('[') {
+                Some(d) => -d.trim_end_matches(']').parse::<i64>().unwrap(),
+                None => w.parse().unwrap(),
+            }).collect()
+        }
+        let d = Die::new(1, 20).with_modifier(Modifier::Advantage);
+        for _ in 1..100 {
+            let r = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+            let rolls = logged(&r);
+            assert_eq!(rolls.len(), 2);
+            assert!(rolls[0] * rolls[1] < 0);
+            assert_eq!(r.value, rolls[0].abs().max(rolls[1].abs()));
+        }
+        let d = Die::new(2, 6).with_modifier(Modifier::Disadvantage);
+        for _ in 1..100 {
+            let r = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+            let rolls = logged(&r);
+            assert_eq!(rolls.len(), 4);
+            let first = rolls[0].abs() + rolls[1].abs();
+            let second = rolls[2].abs() + rolls[3].abs();
+            assert_eq!(r.value, first.min(second));
+        }
+    }
+    #[test]
+    fn test_die_roll_reroll() {
+        let d = Die::new(10, 6).with_modifier(Modifier::RerollRecursive(Compare::new(CmpOp::Le, 5)));
+        assert_eq!(d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value, 60);
+        let d = Die::new(1, 1).with_modifier(Modifier::RerollRecursive(Compare::new(CmpOp::Eq, 1)));
+        let opts = RollOptions { reroll_depth: 3, ..Default::default() };
+        assert_eq!(d.roll_log(&opts, &mut thread_rng()).unwrap().full_log(), "| 1r1r1r1 ");
+        let d = Die::new(1, 1).with_modifier(Modifier::RerollOnce(Compare::new(CmpOp::Eq, 1)));
+        assert_eq!(d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().full_log(), "| 1r1 ");
+    }
+    #[test]
+    fn test_die_roll_pool() {
+        let d = Die::new(5, 1).with_modifier(Modifier::Success(Compare::new(CmpOp::Ge, 1)));
+        let r = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+        assert_eq!(r.value, 5);
+        assert_eq!(r.full_log(), "| 1s 1s 1s 1s 1s ");
+        let d = Die::new(4, 1).with_modifier(Modifier::Success(Compare::new(CmpOp::Gt, 1)))
+            .with_modifier(Modifier::Failure(Compare::new(CmpOp::Eq, 1)))
+            .with_modifier(Modifier::KeepHighest(3));
+        let r = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap();
+        assert_eq!(r.value, -3);
+        assert_eq!(r.full_log(), "| [1] 1f 1f 1f ");
+        let d = Die::new(10, 6).with_modifier(Modifier::Success(Compare::new(CmpOp::Gt, 3)));
+        for _ in 0..100 {
+            let n = d.roll_log(&RollOptions::default(), &mut thread_rng()).unwrap().value;
+            assert!((0..=10).contains(&n));
+        }
+    }
+    #[test]
     fn test_die_fmt() {
         let d = Die::new(3, 4);
         assert!("3d4" == format!("{}", d));
+        let d = Die::new(2, 20).with_modifier(Modifier::KeepLowest(1));
+        assert!("2d20kl1" == format!("{}", d));
+    }
+
+    // Modifier
+    #[test]
+    fn test_modifier_apply() {
+        let mut log = RollLog::new(6);
+        for r in [3, 6, 1, 3] {
+            log.log(r);
+        }
+        Modifier::DropLowest(1).apply(&mut log);
+        assert_eq!(log.kept().collect::<Vec<u64>>(), vec![3, 6, 3]);
+        Modifier::KeepLowest(1).apply(&mut log);
+        assert_eq!(log.kept().collect::<Vec<u64>>(), vec![3]);
+        Modifier::KeepHighest(4).apply(&mut log);
+        assert_eq!(log.kept().collect::<Vec<u64>>(), vec![3]);
+        Modifier::DropHighest(1).apply(&mut log);
+        assert_eq!(log.kept().count(), 0);
     }
 }
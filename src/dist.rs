@@ -0,0 +1,812 @@
+// dist - DnDice
+//   URL: https://github.com/pennbauman/dndice-rs
+//   Author:
+//     Penn Bauman (pennbauman@protonmail.com)
+use std::collections::BTreeMap;
+use std::fmt;
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+use crate::dice::{BinOp, CmpOp, Compare, Die, Expr, Func, Modifier, RollOptions, EXTRA_ROLL_LIMIT};
+
+
+// Limits past which distributions are too slow to calculate, on the number of possible results
+// and on the pairs of results combined by one operation
+const MAX_OUTCOMES: usize = 100_000;
+const MAX_WORK: usize = 10_000_000;
+
+
+/// Error from failed calculation of a distribution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DistributionError {
+    /// Dice with modifiers which cannot be calculated exactly, like exploding dice kept or dropped
+    Unsupported(String),
+    /// Part of an expression with too many possible results
+    TooLarge(String),
+    /// Division which can divide by zero
+    DivideByZero(String),
+}
+impl fmt::Display for DistributionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(s) => write!(f, "Cannot calculate distribution of '{}'", s),
+            Self::TooLarge(s) => write!(f, "Too many possible results from '{}'", s),
+            Self::DivideByZero(s) => write!(f, "Division by zero possible in '{}'", s),
+        }
+    }
+}
+
+
+/// Exact probability distribution of the results of an expression
+///
+/// Each result is counted by the number of equally likely ways it can be rolled, out of a total
+/// number of ways, so probabilities are exact fractions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distribution {
+    min: i64,
+    counts: Vec<BigUint>,
+    total: BigUint,
+}
+impl Distribution {
+    // Distribution of a constant
+    fn point(n: i64) -> Self {
+        Self { min: n, counts: vec![BigUint::one()], total: BigUint::one() }
+    }
+    // Distribution of one die with equally likely results from 1 to size
+    fn uniform(size: u32) -> Self {
+        Self {
+            min: 1,
+            counts: vec![BigUint::one(); size as usize],
+            total: BigUint::from(size),
+        }
+    }
+    // Distribution from the counts of each result, or None with too many possible results
+    fn from_map(map: BTreeMap<i64, BigUint>) -> Option<Self> {
+        let map: BTreeMap<i64, BigUint> = map.into_iter().filter(|(_, c)| !c.is_zero()).collect();
+        let min = *map.keys().next()?;
+        let max = *map.keys().next_back()?;
+        let width = usize::try_from(i128::from(max) - i128::from(min)).ok()?;
+        if width >= MAX_OUTCOMES {
+            return None;
+        }
+        let mut counts = vec![BigUint::zero(); width + 1];
+        let mut total = BigUint::zero();
+        for (n, c) in map {
+            total += &c;
+            counts[(n - min) as usize] = c;
+        }
+        return Some(Self { min, counts, total });
+    }
+
+    /// Lowest possible result
+    pub fn min(&self) -> i64 {
+        self.min
+    }
+    /// Highest possible result
+    pub fn max(&self) -> i64 {
+        self.min + self.counts.len() as i64 - 1
+    }
+    /// Total number of equally likely ways to roll
+    pub fn total(&self) -> &BigUint {
+        &self.total
+    }
+    /// Number of ways to roll exactly a result
+    pub fn count(&self, n: i64) -> BigUint {
+        if n < self.min || n > self.max() {
+            return BigUint::zero();
+        }
+        self.counts[(n - self.min) as usize].clone()
+    }
+    /// Possible results and the number of ways to roll each, from lowest to highest
+    pub fn outcomes(&self) -> impl Iterator<Item = (i64, &BigUint)> + '_ {
+        self.counts.iter().enumerate()
+            .filter(|(_, c)| !c.is_zero())
+            .map(|(i, c)| (self.min + i as i64, c))
+    }
+    /// Probability of rolling exactly a result
+    pub fn probability(&self, n: i64) -> BigRational {
+        self.ratio(self.count(n))
+    }
+    /// Probability of rolling a result at most n
+    pub fn at_most(&self, n: i64) -> BigRational {
+        let count = self.outcomes().take_while(|(x, _)| *x <= n).map(|(_, c)| c).sum();
+        self.ratio(count)
+    }
+    /// Probability of rolling a result at least n
+    pub fn at_least(&self, n: i64) -> BigRational {
+        let count = self.outcomes().skip_while(|(x, _)| *x < n).map(|(_, c)| c).sum();
+        self.ratio(count)
+    }
+    /// Probability mass function, the probability of each possible result
+    pub fn pmf(&self) -> Vec<(i64, BigRational)> {
+        self.outcomes().map(|(n, c)| (n, self.ratio(c.clone()))).collect()
+    }
+    /// Cumulative distribution function, the probability of each possible result or lower
+    pub fn cdf(&self) -> Vec<(i64, BigRational)> {
+        let mut sum = BigUint::zero();
+        let mut result = vec![];
+        for (n, c) in self.outcomes() {
+            sum += c;
+            result.push((n, self.ratio(sum.clone())));
+        }
+        return result;
+    }
+    /// Expected result, the average of many rolls
+    pub fn mean(&self) -> f64 {
+        self.exact_mean().to_f64().unwrap_or(f64::NAN)
+    }
+    /// Variance of the result, the expected square of its difference from the mean
+    pub fn variance(&self) -> f64 {
+        let squares: BigInt = self.outcomes().map(|(n, c)| BigInt::from(n) * n * BigInt::from(c.clone())).sum();
+        let mean = self.exact_mean();
+        let variance = BigRational::new(squares, BigInt::from(self.total.clone())) - &mean * &mean;
+        variance.to_f64().unwrap_or(f64::NAN)
+    }
+    /// Standard deviation of the result
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+    /// Lowest result at least p percent of rolls are at or below
+    pub fn percentile(&self, p: f64) -> i64 {
+        let p = BigRational::from_float(p.clamp(0.0, 100.0) / 100.0).unwrap_or_default();
+        let mut sum = BigUint::zero();
+        for (n, c) in self.outcomes() {
+            sum += c;
+            if self.ratio(sum.clone()) >= p {
+                return n;
+            }
+        }
+        return self.max();
+    }
+    /// Middle result, the 50th percentile
+    pub fn median(&self) -> i64 {
+        self.percentile(50.0)
+    }
+    fn exact_mean(&self) -> BigRational {
+        let sum: BigInt = self.outcomes().map(|(n, c)| BigInt::from(n) * BigInt::from(c.clone())).sum();
+        BigRational::new(sum, BigInt::from(self.total.clone()))
+    }
+    // Fraction of the total ways to roll
+    fn ratio(&self, count: BigUint) -> BigRational {
+        BigRational::new(BigInt::from(count), BigInt::from(self.total.clone()))
+    }
+
+    // Distribution of the sum of two independent results
+    fn add(&self, other: &Distribution) -> Option<Self> {
+        if self.counts.len() * other.counts.len() > MAX_WORK
+                || self.counts.len() + other.counts.len() - 1 > MAX_OUTCOMES {
+            return None;
+        }
+        let mut counts = vec![BigUint::zero(); self.counts.len() + other.counts.len() - 1];
+        for (i, x) in self.counts.iter().enumerate().filter(|(_, x)| !x.is_zero()) {
+            for (j, y) in other.counts.iter().enumerate() {
+                counts[i + j] += x * y;
+            }
+        }
+        return Some(Self {
+            min: self.min.checked_add(other.min)?,
+            counts,
+            total: &self.total * &other.total,
+        });
+    }
+    // Distribution of two independent results combined by a function
+    fn combine<F: Fn(i64, i64) -> Option<i64>>(&self, other: &Distribution, f: F) -> Option<Self> {
+        if self.counts.len() * other.counts.len() > MAX_WORK {
+            return None;
+        }
+        let mut map: BTreeMap<i64, BigUint> = BTreeMap::new();
+        for (x, a) in self.outcomes() {
+            for (y, b) in other.outcomes() {
+                *map.entry(f(x, y)?).or_default() += a * b;
+            }
+        }
+        return Self::from_map(map);
+    }
+    // Distribution of the product of two independent results
+    fn mul(&self, other: &Distribution) -> Option<Self> {
+        self.combine(other, |x, y| x.checked_mul(y))
+    }
+    // Distribution of the negated result
+    fn neg(&self) -> Self {
+        let mut counts = self.counts.clone();
+        counts.reverse();
+        Self { min: -self.max(), counts, total: self.total.clone() }
+    }
+    // Distribution of the result increased by a constant
+    fn shift(&self, n: i64) -> Self {
+        Self { min: self.min + n, counts: self.counts.clone(), total: self.total.clone() }
+    }
+    // Distribution of each result changed by a function
+    fn map<F: Fn(i64) -> i64>(&self, f: F) -> Option<Self> {
+        let mut map: BTreeMap<i64, BigUint> = BTreeMap::new();
+        for (n, c) in self.outcomes() {
+            *map.entry(f(n)).or_default() += c;
+        }
+        return Self::from_map(map);
+    }
+    // Distribution of the higher or lower of two independent results
+    fn best_of_two(&self, higher: bool) -> Self {
+        // Square the chance of every result being at most or at least each value
+        let mut counts = self.counts.clone();
+        if !higher {
+            counts.reverse();
+        }
+        let mut below = BigUint::zero();
+        let mut below_squared = BigUint::zero();
+        for c in counts.iter_mut() {
+            below += &*c;
+            let squared = &below * &below;
+            *c = &squared - &below_squared;
+            below_squared = squared;
+        }
+        if !higher {
+            counts.reverse();
+        }
+        Self { min: self.min, counts, total: &self.total * &self.total }
+    }
+    // Distribution choosing between distributions, each weighted by a number of ways
+    fn mixture(parts: Vec<(BigUint, Distribution)>) -> Option<Self> {
+        let common = parts.iter().fold(BigUint::one(), |l, (_, d)| l.lcm(&d.total));
+        let mut map: BTreeMap<i64, BigUint> = BTreeMap::new();
+        for (weight, d) in &parts {
+            let scale = weight * (&common / &d.total);
+            for (n, c) in d.outcomes() {
+                *map.entry(n).or_default() += c * &scale;
+            }
+        }
+        return Self::from_map(map);
+    }
+}
+
+
+/// Calculate the mean and variance of an expression from its parts, without its distribution
+///
+/// Only expressions of dice without modifiers and with a constant number of sides are supported.
+pub(crate) fn moments(expr: &Expr) -> Option<(f64, f64)> {
+    match expr {
+        Expr::Num(n) => Some((*n as f64, 0.0)),
+        Expr::Var(_) => None,
+        Expr::Die(d) => {
+            if !d.modifiers.is_empty() || bounds(&d.number)?.0 < 0 {
+                return None;
+            }
+            let (n_mean, n_var) = moments(&d.number)?;
+            let (d_mean, d_var) = match *d.sides {
+                Expr::Num(s) if s > 0 => ((s as f64 + 1.0) / 2.0, (s as f64 * s as f64 - 1.0) / 12.0),
+                Expr::Num(_) => (0.0, 0.0),
+                _ => return None,
+            };
+            // Sum of a random number of independent dice
+            Some((n_mean * d_mean, n_mean * d_var + n_var * d_mean * d_mean))
+        },
+        Expr::Neg(e) => moments(e).map(|(m, v)| (-m, v)),
+        Expr::Group(e) => moments(e),
+        Expr::Binary(op, lhs, rhs) => {
+            let (m1, v1) = moments(lhs)?;
+            let (m2, v2) = moments(rhs)?;
+            match op {
+                BinOp::Add => Some((m1 + m2, v1 + v2)),
+                BinOp::Sub => Some((m1 - m2, v1 + v2)),
+                BinOp::Mul => Some((m1 * m2, v1 * v2 + v1 * m2 * m2 + v2 * m1 * m1)),
+                BinOp::Div | BinOp::DivUp | BinOp::DivRound => None,
+            }
+        },
+        Expr::Call(func @ (Func::Floor | Func::Ceil), args) => moments(&func.rounded(args.first()?)),
+        Expr::Call(_, _) => None,
+        Expr::Check(_, total, _) => moments(total),
+    }
+}
+
+/// Calculate the lowest and highest results of an expression from its parts, without its
+/// distribution
+///
+/// Only expressions of dice without modifiers are supported.
+pub(crate) fn bounds(expr: &Expr) -> Option<(i64, i64)> {
+    match expr {
+        Expr::Num(n) => Some((*n, *n)),
+        Expr::Var(_) => None,
+        Expr::Die(d) => {
+            if !d.modifiers.is_empty() {
+                return None;
+            }
+            let (n_min, n_max) = bounds(&d.number)?;
+            let (s_min, s_max) = bounds(&d.sides)?;
+            let (n_min, n_max, s_min, s_max) = (n_min.max(0), n_max.max(0), s_min.max(0), s_max.max(0));
+            // Each die rolls at least 1, unless it can have no sides
+            let min = if s_min == 0 { 0 } else { n_min };
+            Some((min, n_max.checked_mul(s_max)?))
+        },
+        Expr::Neg(e) => bounds(e).map(|(lo, hi)| (-hi, -lo)),
+        Expr::Group(e) => bounds(e),
+        Expr::Binary(op, lhs, rhs) => {
+            let (lo1, hi1) = bounds(lhs)?;
+            let (lo2, hi2) = bounds(rhs)?;
+            match op {
+                BinOp::Add => Some((lo1.checked_add(lo2)?, hi1.checked_add(hi2)?)),
+                BinOp::Sub => Some((lo1.checked_sub(hi2)?, hi1.checked_sub(lo2)?)),
+                BinOp::Mul => {
+                    let corners = [lo1.checked_mul(lo2)?, lo1.checked_mul(hi2)?,
+                        hi1.checked_mul(lo2)?, hi1.checked_mul(hi2)?];
+                    Some((*corners.iter().min()?, *corners.iter().max()?))
+                },
+                // Division rounds monotonically, so the result is bounded by its corners
+                BinOp::Div | BinOp::DivUp | BinOp::DivRound => {
+                    if lo2 <= 0 && hi2 >= 0 {
+                        return None;
+                    }
+                    let corners = [op.apply(lo1, lo2)?, op.apply(lo1, hi2)?,
+                        op.apply(hi1, lo2)?, op.apply(hi1, hi2)?];
+                    Some((*corners.iter().min()?, *corners.iter().max()?))
+                },
+            }
+        },
+        Expr::Call(func, args) => {
+            let mut lows = vec![];
+            let mut highs = vec![];
+            for arg in args {
+                let (lo, hi) = bounds(&func.rounded(arg))?;
+                lows.push(lo);
+                highs.push(hi);
+            }
+            match func {
+                Func::Abs => {
+                    let (lo, hi) = (*lows.first()?, *highs.first()?);
+                    if lo >= 0 {
+                        Some((lo, hi))
+                    } else if hi <= 0 {
+                        Some((hi.saturating_abs(), lo.saturating_abs()))
+                    } else {
+                        Some((0, lo.saturating_abs().max(hi)))
+                    }
+                },
+                // Other functions never decrease when an argument increases
+                _ => Some((func.apply(&lows)?, func.apply(&highs)?)),
+            }
+        },
+        Expr::Check(_, total, _) => bounds(total),
+    }
+}
+
+
+/// Calculate the distribution of results from an expression
+pub(crate) fn distribution(expr: &Expr, opts: &RollOptions) -> Result<Distribution, DistributionError> {
+    let too_large = || DistributionError::TooLarge(expr.to_string());
+    match expr {
+        Expr::Num(n) => Ok(Distribution::point(*n)),
+        Expr::Var(_) => Err(DistributionError::Unsupported(expr.to_string())),
+        Expr::Die(d) => die_distribution(d, opts),
+        Expr::Neg(e) => Ok(distribution(e, opts)?.neg()),
+        Expr::Group(e) => distribution(e, opts),
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = distribution(lhs, opts)?;
+            let rhs = distribution(rhs, opts)?;
+            let result = match op {
+                BinOp::Add => lhs.add(&rhs),
+                BinOp::Sub => lhs.add(&rhs.neg()),
+                BinOp::Mul => lhs.mul(&rhs),
+                BinOp::Div | BinOp::DivUp | BinOp::DivRound => {
+                    if !rhs.count(0).is_zero() {
+                        return Err(DistributionError::DivideByZero(expr.to_string()));
+                    }
+                    lhs.combine(&rhs, |x, y| op.apply(x, y))
+                },
+            };
+            return result.ok_or_else(too_large);
+        },
+        Expr::Call(func, args) => {
+            if !func.accepts(args.len()) {
+                return Err(DistributionError::Unsupported(expr.to_string()));
+            }
+            let mut result = distribution(&func.rounded(&args[0]), opts)?;
+            for (i, arg) in args.iter().enumerate().skip(1) {
+                let arg = distribution(arg, opts)?;
+                // Clamping raises the value to its lower limit, then lowers it to its upper limit
+                let higher = *func == Func::Max || (*func == Func::Clamp && i == 1);
+                result = if higher {
+                    result.combine(&arg, |x, y| Some(x.max(y)))
+                } else {
+                    result.combine(&arg, |x, y| Some(x.min(y)))
+                }.ok_or_else(too_large)?;
+            }
+            if *func == Func::Abs {
+                result = result.map(i64::saturating_abs).ok_or_else(too_large)?;
+            }
+            return Ok(result);
+        },
+        Expr::Check(_, total, _) => distribution(total, opts),
+    }
+}
+
+
+// Distribution of a set of dice, over every quantity and size they could have
+fn die_distribution(die: &Die, opts: &RollOptions) -> Result<Distribution, DistributionError> {
+    let number = distribution(&die.number, opts)?;
+    let sides = distribution(&die.sides, opts)?;
+    let mut parts = vec![];
+    for (n, n_count) in number.outcomes() {
+        for (s, s_count) in sides.outcomes() {
+            parts.push((n_count * s_count, set_distribution(die, n.max(0), s.max(0), opts)?));
+        }
+    }
+    return Distribution::mixture(parts).ok_or_else(|| DistributionError::TooLarge(die.to_string()));
+}
+
+// Distribution of a set of dice with a known quantity and size
+fn set_distribution(die: &Die, count: i64, range: i64, opts: &RollOptions)
+        -> Result<Distribution, DistributionError> {
+    let too_large = || DistributionError::TooLarge(die.to_string());
+    let count = usize::try_from(count).map_err(|_| too_large())?;
+    if count > MAX_OUTCOMES || range > MAX_OUTCOMES as i64 {
+        return Err(too_large());
+    }
+    // The limit on extra rolls is shared by every die in the set, so sets which could reach it are
+    // not calculated
+    let rolls = |c: Compare| (1..=range).any(|n| c.matches(n));
+    let rerolls = die.reroll(opts).filter(|(_, t)| rolls(*t)).map_or(0, |(d, _)| u64::from(d));
+    let explodes = die.explode().filter(|(_, t)| range > 0 && t.is_none_or(rolls))
+        .map_or(0, |_| u64::from(opts.explode_depth));
+    if (count as u64).saturating_mul(rerolls + explodes) > u64::from(EXTRA_ROLL_LIMIT) {
+        return Err(DistributionError::Unsupported(die.to_string()));
+    }
+    let keeps = die.modifiers.iter().any(|m| m.is_keep());
+    // Pools count the score of each die instead of summing their values
+    let pool = die.pool();
+    let score = |n: i64| match pool {
+        Some((success, failure)) => {
+            if success.matches(n) {
+                1
+            } else if failure.is_some_and(|f| f.matches(n)) {
+                -1
+            } else {
+                0
+            }
+        },
+        None => n,
+    };
+    // Distribution of each die, as its value or as its score when exploding into separate dice
+    let mut scored = false;
+    let die_dist = if range == 0 {
+        Distribution::point(0)
+    } else {
+        let plain = Distribution::uniform(range as u32);
+        let first = match die.reroll(opts) {
+            Some((depth, target)) => reroll(&plain, target, depth),
+            None => plain.clone(),
+        };
+        match die.explode() {
+            None => first,
+            Some((compound, target)) => {
+                let target = target.unwrap_or(Compare::new(CmpOp::Eq, range));
+                if !compound && keeps {
+                    return Err(DistributionError::Unsupported(die.to_string()));
+                }
+                scored = !compound;
+                let score = |n: i64| if compound { n } else { score(n) };
+                // Each extra roll continues exploding until no explosions are left
+                let mut rest: Option<Distribution> = None;
+                for _ in 0..opts.explode_depth {
+                    rest = Some(explode(&plain, target, rest.as_ref(), &score).ok_or_else(too_large)?);
+                }
+                explode(&first, target, rest.as_ref(), &score).ok_or_else(too_large)?
+            },
+        }
+    };
+    let mut set = if keeps {
+        keep_distribution(die, &die_dist, count, &score).ok_or_else(too_large)?
+    } else {
+        let die_dist = if scored || pool.is_none() { die_dist } else { die_dist.map(score).ok_or_else(too_large)? };
+        let mut set = Distribution::point(0);
+        for _ in 0..count {
+            set = set.add(&die_dist).ok_or_else(too_large)?;
+        }
+        set
+    };
+    if let Some(m) = die.modifiers.iter().find(|m| m.is_advantage()) {
+        set = set.best_of_two(*m == Modifier::Advantage);
+    }
+    return Ok(set);
+}
+
+// Distribution of a die rerolled up to depth times while matching a target
+fn reroll(plain: &Distribution, target: Compare, depth: u32) -> Distribution {
+    let mut current = plain.clone();
+    for _ in 0..depth {
+        let matched: BigUint = current.outcomes()
+            .filter(|(n, _)| target.matches(*n))
+            .map(|(_, c)| c)
+            .sum();
+        if matched.is_zero() {
+            break;
+        }
+        // Matching rolls are replaced by a new roll
+        let mut counts = vec![];
+        for (i, c) in current.counts.iter().enumerate() {
+            let n = current.min + i as i64;
+            let mut count = &matched * plain.count(n);
+            if !target.matches(n) {
+                count += c * &plain.total;
+            }
+            counts.push(count);
+        }
+        current = Distribution { min: current.min, counts, total: &current.total * &plain.total };
+    }
+    return current;
+}
+
+// Distribution of the scores of a roll and, when it matches the target, the following rolls
+fn explode<F: Fn(i64) -> i64>(roll: &Distribution, target: Compare, rest: Option<&Distribution>,
+        score: &F) -> Option<Distribution> {
+    let mut parts = vec![];
+    for (n, c) in roll.outcomes() {
+        let part = match rest {
+            Some(rest) if target.matches(n) => rest.shift(score(n)),
+            _ => Distribution::point(score(n)),
+        };
+        parts.push((c.clone(), part));
+    }
+    return Distribution::mixture(parts);
+}
+
+// Distribution of the scores of dice kept after keep and drop modifiers
+fn keep_distribution<F: Fn(i64) -> i64>(die: &Die, die_dist: &Distribution, count: usize,
+        score: &F) -> Option<Distribution> {
+    let faces: Vec<(i64, &BigUint)> = die_dist.outcomes().collect();
+    if faces.len() * (count + 1) * (count + 1) > MAX_WORK {
+        return None;
+    }
+    // Number of lowest and highest dice dropped, kept dice always being a run of sorted dice
+    let (mut low, mut high) = (0, 0);
+    for m in &die.modifiers {
+        let kept = count - low - high;
+        match *m {
+            Modifier::KeepHighest(n) => low += kept.saturating_sub(n as usize),
+            Modifier::KeepLowest(n) => high += kept.saturating_sub(n as usize),
+            Modifier::DropHighest(n) => high += (n as usize).min(kept),
+            Modifier::DropLowest(n) => low += (n as usize).min(kept),
+            _ => (),
+        }
+    }
+    // Ways to choose which dice roll each face
+    let mut binomial = vec![vec![BigUint::one()]];
+    for i in 1..=count {
+        let mut row = vec![BigUint::one(); i + 1];
+        for j in 1..i {
+            row[j] = &binomial[i - 1][j - 1] + &binomial[i - 1][j];
+        }
+        binomial.push(row);
+    }
+    // Ways to reach each score with some dice placed, going through faces from highest to lowest
+    let mut placed: Vec<BTreeMap<i64, BigUint>> = vec![BTreeMap::new(); count + 1];
+    placed[0].insert(0, BigUint::one());
+    for (n, weight) in faces.into_iter().rev() {
+        let mut powers = vec![BigUint::one()];
+        for j in 0..count {
+            powers.push(&powers[j] * weight);
+        }
+        let mut next: Vec<BTreeMap<i64, BigUint>> = vec![BTreeMap::new(); count + 1];
+        for (c, sums) in placed.iter().enumerate() {
+            for (sum, ways) in sums {
+                for j in 0..=(count - c) {
+                    // Dice in sorted places c to c + j roll this face, some of which are kept
+                    let kept = (c + j).min(count - low).saturating_sub(c.max(high));
+                    let ways = ways * &binomial[count - c][j] * &powers[j];
+                    *next[c + j].entry(sum + score(n) * kept as i64).or_default() += ways;
+                }
+            }
+        }
+        placed = next;
+    }
+    return Distribution::from_map(placed.pop()?);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dist(text: &str) -> Distribution {
+        distribution(&text.parse().unwrap(), &RollOptions::default()).unwrap()
+    }
+    fn ratio(n: i64, d: i64) -> BigRational {
+        BigRational::new(n.into(), d.into())
+    }
+
+    #[test]
+    fn test_distribution_point() {
+        let d = dist("4 - 7");
+        assert_eq!((d.min(), d.max()), (-3, -3));
+        assert_eq!(d.probability(-3), ratio(1, 1));
+        assert_eq!(d.probability(3), ratio(0, 1));
+    }
+    #[test]
+    fn test_distribution_sum() {
+        let d = dist("2d6");
+        assert_eq!((d.min(), d.max()), (2, 12));
+        assert_eq!(d.total(), &BigUint::from(36u32));
+        assert_eq!(d.probability(7), ratio(1, 6));
+        assert_eq!(d.at_least(8), ratio(15, 36));
+        assert_eq!(d.at_most(4), ratio(1, 6));
+        assert_eq!(d.cdf().last().unwrap().1, ratio(1, 1));
+        assert_eq!(d.pmf().len(), 11);
+        let d = dist("8d6");
+        assert_eq!(d.at_least(30), ratio(638_543, 1_679_616));
+        let d = dist("-1d4 + 2x1d2");
+        assert_eq!((d.min(), d.max()), (-2, 3));
+        assert_eq!(d.probability(0), ratio(1, 4));
+    }
+    #[test]
+    fn test_distribution_mul() {
+        let d = dist("1d4x1d4");
+        assert_eq!((d.min(), d.max()), (1, 16));
+        assert_eq!(d.probability(4), ratio(3, 16));
+        assert_eq!(d.probability(5), ratio(0, 1));
+    }
+    #[test]
+    fn test_distribution_div() {
+        let d = dist("1d6/2");
+        assert_eq!(d.pmf(), vec![(0, ratio(1, 6)), (1, ratio(1, 3)), (2, ratio(1, 3)), (3, ratio(1, 6))]);
+        let d = dist("1d6/^2");
+        assert_eq!(d.pmf(), vec![(1, ratio(1, 3)), (2, ratio(1, 3)), (3, ratio(1, 3))]);
+        let d = dist("2d6/~(1d2 + 1)");
+        assert_eq!((d.min(), d.max()), (1, 6));
+        assert!(matches!(distribution(&"1d6/(1d3 - 2)".parse().unwrap(), &RollOptions::default()),
+            Err(DistributionError::DivideByZero(s)) if s == "1d6/(1d3 - 2)"));
+    }
+    #[test]
+    fn test_distribution_func() {
+        let d = dist("max(1, 1d4 - 1)");
+        assert_eq!(d.pmf(), vec![(1, ratio(1, 2)), (2, ratio(1, 4)), (3, ratio(1, 4))]);
+        let d = dist("min(1d4, 1d4)");
+        assert_eq!(d.pmf(), vec![(1, ratio(7, 16)), (2, ratio(5, 16)), (3, ratio(3, 16)),
+            (4, ratio(1, 16))]);
+        let d = dist("ceil(1d4/2)");
+        assert_eq!(d.pmf(), vec![(1, ratio(1, 2)), (2, ratio(1, 2))]);
+        let d = dist("floor((1d4/^2))");
+        assert_eq!(d.pmf(), vec![(0, ratio(1, 4)), (1, ratio(1, 2)), (2, ratio(1, 4))]);
+        let d = dist("abs(1d4 - 1d4)");
+        assert_eq!(d.pmf(), vec![(0, ratio(1, 4)), (1, ratio(3, 8)), (2, ratio(1, 4)),
+            (3, ratio(1, 8))]);
+        let d = dist("clamp(1d6, 2, 5)");
+        assert_eq!(d.pmf(), vec![(2, ratio(1, 3)), (3, ratio(1, 6)), (4, ratio(1, 6)),
+            (5, ratio(1, 3))]);
+        assert_eq!(dist("max(1, 2)d6"), dist("2d6"));
+        assert_eq!(dist("1d20 + 5 >= 1d20"), dist("1d20 + 5"));
+        assert!(matches!(distribution(&Expr::Call(Func::Max, vec![]), &RollOptions::default()),
+            Err(DistributionError::Unsupported(s)) if s == "max()"));
+    }
+    #[test]
+    fn test_distribution_nested() {
+        let d = dist("(1d2)d4");
+        assert_eq!(d.probability(1), ratio(1, 8));
+        let d = dist("1d(1d2 + 1)");
+        assert_eq!(d.probability(3), ratio(1, 6));
+        assert_eq!(dist("0d6 + 1d0 + (0 - 2)d4"), Distribution::point(0));
+    }
+    #[test]
+    fn test_distribution_keep() {
+        let d = dist("4d6kh3");
+        assert_eq!((d.min(), d.max()), (3, 18));
+        assert_eq!(d.probability(18), ratio(21, 1296));
+        assert_eq!(d.probability(3), ratio(1, 1296));
+        assert_eq!(dist("4d6dl1"), d);
+        let d = dist("3d6kh2kl1");
+        assert_eq!(d.probability(1), ratio(16, 216));
+        assert_eq!(dist("3d6dh1dl1"), d);
+    }
+    #[test]
+    fn test_distribution_advantage() {
+        let d = dist("1d20adv");
+        assert_eq!(d.probability(20), ratio(39, 400));
+        assert_eq!(d.probability(1), ratio(1, 400));
+        let d = dist("1d20dis");
+        assert_eq!(d.probability(1), ratio(39, 400));
+        assert_eq!(d.at_least(11), ratio(1, 4));
+    }
+    #[test]
+    fn test_distribution_reroll() {
+        let d = dist("1d6r1");
+        assert_eq!(d.probability(1), ratio(1, 36));
+        assert_eq!(d.probability(2), ratio(7, 36));
+        let opts = RollOptions { reroll_depth: 2, ..Default::default() };
+        let d = distribution(&"1d6rr<3".parse().unwrap(), &opts).unwrap();
+        assert_eq!(d.probability(1), ratio(1, 54));
+        assert_eq!(d.probability(3), ratio(13, 54));
+    }
+    #[test]
+    fn test_distribution_explode() {
+        let d = dist("1d6!");
+        assert_eq!(d.probability(6), ratio(0, 1));
+        assert_eq!(d.probability(7), ratio(1, 36));
+        assert_eq!(d.probability(13), ratio(1, 216));
+        assert_eq!(dist("1d6!!"), d);
+        let d = dist("1d6!>4");
+        assert_eq!(d.probability(5), ratio(0, 1));
+        assert_eq!(d.probability(7), ratio(1, 18));
+        let opts = RollOptions { explode_depth: 2, ..Default::default() };
+        let d = distribution(&"1d2!".parse().unwrap(), &opts).unwrap();
+        assert_eq!(d.probability(6), ratio(1, 8));
+        assert_eq!(d.probability(5), ratio(1, 8));
+        let d = dist("1d1!>1 + 1d1!");
+        assert_eq!(d.probability(102), ratio(1, 1));
+        // Sets which could roll more extra dice than the limit are not calculated
+        assert_eq!(dist("10d1!").probability(1010), ratio(1, 1));
+        assert!(matches!(distribution(&"11d6!".parse().unwrap(), &RollOptions::default()),
+            Err(DistributionError::Unsupported(s)) if s == "11d6!"));
+        assert!(distribution(&"4d6rr<7".parse().unwrap(), &opts).is_ok());
+        assert!(distribution(&"20d6rr<1 + 20d6!>6".parse().unwrap(), &opts).is_ok());
+    }
+    #[test]
+    fn test_distribution_pool() {
+        let d = dist("2d6>4");
+        assert_eq!(d.probability(2), ratio(1, 9));
+        assert_eq!(d.probability(0), ratio(4, 9));
+        let d = dist("1d6>4f1");
+        assert_eq!(d.probability(-1), ratio(1, 6));
+        let d = dist("3d6kh1>=6");
+        assert_eq!(d.probability(1), ratio(91, 216));
+        let d = dist("1d6>5!");
+        assert_eq!(d.probability(2), ratio(5, 216));
+        assert_eq!(d.probability(1), ratio(5, 36));
+    }
+    #[test]
+    fn test_distribution_stats() {
+        let d = dist("2d6");
+        assert_eq!(d.mean(), 7.0);
+        assert!((d.variance() - 35.0 / 6.0).abs() < 1e-9);
+        assert!((d.std_dev() - (35.0f64 / 6.0).sqrt()).abs() < 1e-9);
+        assert_eq!(d.median(), 7);
+        assert_eq!(d.percentile(0.0), 2);
+        assert_eq!(d.percentile(58.3), 7);
+        assert_eq!(d.percentile(58.4), 8);
+        assert_eq!(d.percentile(100.0), 12);
+        let d = dist("4d6kh3");
+        assert!((d.mean() - 15869.0 / 1296.0).abs() < 1e-9);
+    }
+    #[test]
+    fn test_moments() {
+        let moments = |text: &str| moments(&text.parse().unwrap());
+        assert_eq!(moments("2d6 + 3"), Some((10.0, 70.0 / 12.0)));
+        assert_eq!(moments("-1d4x2"), Some((-5.0, 5.0)));
+        assert_eq!(moments("1d0 + 0d4"), Some((0.0, 0.0)));
+        assert_eq!(moments("4d6kh3"), None);
+        assert_eq!(moments("1d(1d6)"), None);
+        // Compared with the distribution where they are found analytically
+        assert_eq!(moments("max(1, 1d6)"), None);
+        for text in ["(1d4)d6 - 3", "1d6x1d8", "(1d2 + 1)d4x2 - 1d10", "floor(2d6 + 1)"] {
+            let (mean, variance) = moments(text).unwrap();
+            let d = dist(text);
+            assert!((mean - d.mean()).abs() < 1e-9);
+            assert!((variance - d.variance()).abs() < 1e-9);
+        }
+    }
+    #[test]
+    fn test_bounds() {
+        let bounds = |text: &str| bounds(&text.parse().unwrap());
+        assert_eq!(bounds("2d6 + 3"), Some((5, 15)));
+        assert_eq!(bounds("-1d4x2 - 1"), Some((-9, -3)));
+        assert_eq!(bounds("(1d3 - 2)d(1d4 - 1)"), Some((0, 3)));
+        assert_eq!(bounds("2d6dl1"), None);
+        assert_eq!(bounds("1d6/(1d3 - 2)"), None);
+        for text in ["(1d4)d6 - 3", "1d6x(0 - 1d8)", "(1d2 + 1)d4x2 - 1d10", "3d6/(1d4 - 5)",
+                "-1d20/^1d3 + 2d6/~2", "max(1, 1d4 - 1)", "min(1d6, 2d4)", "abs(1d4 - 1d6)",
+                "abs(1d4 + 1)", "-abs(1d4)", "clamp(3d6, 1d4 + 4, 15)", "ceil(-1d7/2)"] {
+            let d = dist(text);
+            assert_eq!(bounds(text), Some((d.min(), d.max())));
+        }
+    }
+    #[test]
+    fn test_distribution_err() {
+        let opts = RollOptions::default();
+        assert!(matches!(distribution(&"4d6!kh3".parse().unwrap(), &opts),
+            Err(DistributionError::Unsupported(s)) if s == "4d6!kh3"));
+        assert!(matches!(distribution(&"1d6 + @x".parse().unwrap(), &opts),
+            Err(DistributionError::Unsupported(s)) if s == "@x"));
+        assert_eq!(moments(&"1d6 + @x".parse().unwrap()), None);
+        assert_eq!(bounds(&"1d6 + @x".parse().unwrap()), None);
+        assert!(matches!(distribution(&"1d999999999".parse().unwrap(), &opts),
+            Err(DistributionError::TooLarge(s)) if s == "1d999999999"));
+        assert!(matches!(distribution(&"2 + 1d1000x1d1000".parse().unwrap(), &opts),
+            Err(DistributionError::TooLarge(s)) if s == "1d1000x1d1000"));
+    }
+}
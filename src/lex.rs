@@ -0,0 +1,243 @@
+// lex - DnDice
+//   URL: https://github.com/pennbauman/dndice-rs
+//   Author:
+//     Penn Bauman (pennbauman@protonmail.com)
+use std::fmt;
+use crate::dice::{BinOp, CmpOp};
+use crate::parse::DiceParseError;
+
+
+/// Byte range of a token in the parsed text
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+    /// Span covering both this span and another
+    pub fn join(&self, other: Span) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+
+// Words used in expressions, listed longest first so shorter words never match their start
+const WORDS: [&str; 16] = ["floor", "clamp", "ceil", "min", "max", "abs", "adv", "dis", "kh", "kl",
+    "dh", "dl", "rr", "ro", "r", "f"];
+
+
+// Kinds of Tokens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Num(i64),
+    Word(&'static str),
+    Var(String),
+    Die,
+    Plus,
+    Minus,
+    Star,
+    /// Division, rounding as its operator does
+    Slash(BinOp),
+    Bang,
+    Cmp(CmpOp),
+    LParen,
+    RParen,
+    Comma,
+}
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{}", n),
+            Self::Word(w) => write!(f, "{}", w),
+            Self::Var(v) => write!(f, "@{}", v),
+            Self::Die => write!(f, "d"),
+            Self::Plus => write!(f, "+"),
+            Self::Minus => write!(f, "-"),
+            Self::Star => write!(f, "*"),
+            Self::Slash(op) => write!(f, "{}", op),
+            Self::Bang => write!(f, "!"),
+            Self::Cmp(op) => write!(f, "{}", op),
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Comma => write!(f, ","),
+        }
+    }
+}
+
+
+// Token and its location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+
+/// Split text into tokens, skipping whitespace
+pub fn tokenize(text: &str) -> Result<Vec<Token>, DiceParseError> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if let Some(w) = WORDS.iter().find(|w| text[start..].starts_with(*w)) {
+            for _ in 1..w.len() {
+                chars.next();
+            }
+            tokens.push(Token {
+                kind: TokenKind::Word(w),
+                span: Span::new(start, start + w.len()),
+            });
+            continue;
+        }
+        let kind = match c {
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' | 'x' => TokenKind::Star,
+            '/' => match chars.next_if(|(_, n)| *n == '^' || *n == '~') {
+                Some((_, '^')) => TokenKind::Slash(BinOp::DivUp),
+                Some(_) => TokenKind::Slash(BinOp::DivRound),
+                None => TokenKind::Slash(BinOp::Div),
+            },
+            'd' | 'D' => TokenKind::Die,
+            '!' => TokenKind::Bang,
+            '=' => TokenKind::Cmp(CmpOp::Eq),
+            '<' | '>' => {
+                let or_equal = chars.next_if(|(_, n)| *n == '=').is_some();
+                match (c, or_equal) {
+                    ('<', false) => TokenKind::Cmp(CmpOp::Lt),
+                    ('<', true) => TokenKind::Cmp(CmpOp::Le),
+                    ('>', false) => TokenKind::Cmp(CmpOp::Gt),
+                    _ => TokenKind::Cmp(CmpOp::Ge),
+                }
+            },
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            ',' => TokenKind::Comma,
+            '@' => {
+                let mut name = String::new();
+                let is_name = |(_, n): &(usize, char)| n.is_ascii_alphanumeric() || *n == '_';
+                while let Some((_, n)) = chars.next_if(is_name) {
+                    name.push(n);
+                }
+                if name.is_empty() {
+                    return Err(DiceParseError::InvalidChar('@'));
+                }
+                TokenKind::Var(name)
+            },
+            c if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((i, d)) = chars.peek() {
+                    if !d.is_ascii_digit() {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                match text[start..end].parse::<i64>() {
+                    Ok(n) => TokenKind::Num(n),
+                    Err(_) => return Err(DiceParseError::InvalidNumber(
+                        text[start..end].to_string()
+                    )),
+                }
+            },
+            c if c.is_whitespace() => continue,
+            c => return Err(DiceParseError::InvalidChar(c)),
+        };
+        let end = match chars.peek() {
+            Some((i, _)) => *i,
+            None => text.len(),
+        };
+        tokens.push(Token { kind, span: Span::new(start, end) });
+    }
+    return Ok(tokens);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_join() {
+        let s = Span::new(4, 6).join(Span::new(1, 2));
+        assert_eq!(s, Span::new(1, 6));
+    }
+    #[test]
+    fn test_tokenize_slash() {
+        let tokens = tokenize("1/2 /^3/~ 4").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Num(1), TokenKind::Slash(BinOp::Div), TokenKind::Num(2),
+            TokenKind::Slash(BinOp::DivUp), TokenKind::Num(3), TokenKind::Slash(BinOp::DivRound),
+            TokenKind::Num(4)]);
+        assert_eq!(tokens[3].span, Span::new(4, 6));
+    }
+    #[test]
+    fn test_tokenize_var() {
+        let tokens = tokenize("1d20+@str_mod2 *@x").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Num(1), TokenKind::Die, TokenKind::Num(20),
+            TokenKind::Plus, TokenKind::Var(String::from("str_mod2")), TokenKind::Star,
+            TokenKind::Var(String::from("x"))]);
+        assert_eq!(tokens[4].span, Span::new(5, 14));
+        assert!(matches!(tokenize("1 + @ 2"), Err(DiceParseError::InvalidChar('@'))));
+    }
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("12d4 *(3)- x").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Num(12), TokenKind::Die, TokenKind::Num(4),
+            TokenKind::Star, TokenKind::LParen, TokenKind::Num(3), TokenKind::RParen,
+            TokenKind::Minus, TokenKind::Star]);
+    }
+    #[test]
+    fn test_tokenize_spans() {
+        let tokens = tokenize(" 10 +D8").unwrap();
+        assert_eq!(tokens[0].span, Span::new(1, 3));
+        assert_eq!(tokens[1].span, Span::new(4, 5));
+        assert_eq!(tokens[2].span, Span::new(5, 6));
+        assert_eq!(tokens[3].span, Span::new(6, 7));
+    }
+    #[test]
+    fn test_tokenize_words() {
+        let tokens = tokenize("4d6dl1dd").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Num(4), TokenKind::Die, TokenKind::Num(6),
+            TokenKind::Word("dl"), TokenKind::Num(1), TokenKind::Die, TokenKind::Die]);
+        assert_eq!(tokens[3].span, Span::new(3, 5));
+        let tokens = tokenize("adv d20dis").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Word("adv"), TokenKind::Die, TokenKind::Num(20),
+            TokenKind::Word("dis")]);
+        let tokens = tokenize("rrror").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Word("rr"), TokenKind::Word("ro"),
+            TokenKind::Word("r")]);
+    }
+    #[test]
+    fn test_tokenize_func() {
+        let tokens = tokenize("floor(@n/2), ceil fmax").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Word("floor"), TokenKind::LParen,
+            TokenKind::Var(String::from("n")), TokenKind::Slash(BinOp::Div), TokenKind::Num(2),
+            TokenKind::RParen, TokenKind::Comma, TokenKind::Word("ceil"), TokenKind::Word("f"),
+            TokenKind::Word("max")]);
+        assert_eq!(tokens[0].span, Span::new(0, 5));
+    }
+    #[test]
+    fn test_tokenize_compare() {
+        let tokens = tokenize("!!>= 5<=<> =!").unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(kinds, vec![TokenKind::Bang, TokenKind::Bang, TokenKind::Cmp(CmpOp::Ge),
+            TokenKind::Num(5), TokenKind::Cmp(CmpOp::Le), TokenKind::Cmp(CmpOp::Lt),
+            TokenKind::Cmp(CmpOp::Gt), TokenKind::Cmp(CmpOp::Eq), TokenKind::Bang]);
+        assert_eq!(tokens[2].span, Span::new(2, 4));
+    }
+    #[test]
+    fn test_tokenize_err() {
+        assert!(matches!(tokenize("1d4 + g"), Err(DiceParseError::InvalidChar('g'))));
+        assert!(matches!(tokenize("99999999999999999999"),
+            Err(DiceParseError::InvalidNumber(_))));
+    }
+}
@@ -2,30 +2,45 @@
 //   URL: https://github.com/pennbauman/dndice-rs
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
+use std::collections::HashMap;
 use std::str::FromStr;
+use rand::Rng;
 
+mod abilities;
+pub use abilities::{Abilities, Ability, Assignment, Class};
+mod aliases;
+pub use aliases::{AliasError, Aliases};
 mod dice;
-use dice::DiceSet;
+pub use dice::{BinOp, CmpOp, Compare, CritMode, Die, Expr, Func, Modifier, RollError, CRIT_RANGE,
+    DICE_LIMIT, EXPLODE_DEPTH, EXTRA_ROLL_LIMIT, REROLL_DEPTH};
+use dice::RollOptions;
+mod dist;
+pub use dist::{Distribution, DistributionError};
+mod lex;
 mod parse;
 pub use parse::DiceParseError;
 mod log;
-use log::DiceRoll;
+pub use log::{Crit, DiceRoll, DieRoll, Outcome, RollPart, SetRoll};
 mod scores;
-pub use scores::Scores;
+pub use scores::{Constraints, PointBuy, PointBuyError, Scores, ScoresError, MAX_ATTEMPTS};
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dice {
     name: Option<String>,
-    set: DiceSet,
+    expr: Expr,
     history: Vec<DiceRoll>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    options: RollOptions,
 }
 impl Dice {
     pub fn new() -> Self {
         Self {
             name: None,
-            set: DiceSet::new(),
+            expr: Expr::Num(0),
             history: vec![],
+            options: RollOptions::default(),
         }
     }
     pub fn from<S: ToString>(text: S) -> Result<Self, <Dice as FromStr>::Err> {
@@ -34,36 +49,170 @@ impl Dice {
     pub fn name<S: ToString>(&mut self, new_name: S) {
         self.name = Some(new_name.to_string());
     }
-    pub fn roll(&mut self) -> i64 {
-        let result = self.set.roll();
-        let r = result.num();
-        self.history.push(result);
-        return r;
+    /// Set the most extra dice one exploding die can roll in a row, `EXPLODE_DEPTH` by default
+    pub fn explode_depth(&mut self, depth: u32) {
+        self.options.explode_depth = depth;
+    }
+    /// Set the most times one die can be rerolled by `rr`, `REROLL_DEPTH` by default
+    pub fn reroll_depth(&mut self, depth: u32) {
+        self.options.reroll_depth = depth;
+    }
+    /// Set the lowest natural roll on a d20 which is a critical hit, `CRIT_RANGE` by default, such
+    /// as 19 for a crit on 19 or 20
+    pub fn crit_range(&mut self, lowest: u32) {
+        self.options.crit_range = lowest;
+    }
+    /// Expression rolled by these dice
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+    /// Calculate the exact probability distribution of results from rolling
+    pub fn distribution(&self) -> Result<Distribution, DistributionError> {
+        dist::distribution(&self.expr, &self.options)
+    }
+    /// Expected result, the average of many rolls
+    pub fn mean(&self) -> Result<f64, DistributionError> {
+        match dist::moments(&self.expr) {
+            Some((mean, _)) => Ok(mean),
+            None => Ok(self.distribution()?.mean()),
+        }
+    }
+    /// Variance of the result, the expected square of its difference from the mean
+    pub fn variance(&self) -> Result<f64, DistributionError> {
+        match dist::moments(&self.expr) {
+            Some((_, variance)) => Ok(variance),
+            None => Ok(self.distribution()?.variance()),
+        }
+    }
+    /// Standard deviation of the result
+    pub fn std_dev(&self) -> Result<f64, DistributionError> {
+        Ok(self.variance()?.sqrt())
+    }
+    /// Lowest possible result
+    pub fn min(&self) -> Result<i64, DistributionError> {
+        match dist::bounds(&self.expr) {
+            Some((min, _)) => Ok(min),
+            None => Ok(self.distribution()?.min()),
+        }
+    }
+    /// Highest possible result
+    pub fn max(&self) -> Result<i64, DistributionError> {
+        match dist::bounds(&self.expr) {
+            Some((_, max)) => Ok(max),
+            None => Ok(self.distribution()?.max()),
+        }
+    }
+    /// Middle result, the 50th percentile
+    pub fn median(&self) -> Result<i64, DistributionError> {
+        Ok(self.distribution()?.median())
+    }
+    /// Lowest result at least p percent of rolls are at or below
+    pub fn percentile(&self, p: f64) -> Result<i64, DistributionError> {
+        Ok(self.distribution()?.percentile(p))
+    }
+    /// Roll the dice, failing if they have variables, which must be given values with
+    /// `roll_with_context`, or divide by zero
+    pub fn roll(&mut self) -> Result<i64, RollError> {
+        self.roll_with(&mut rand::thread_rng())
+    }
+    /// Roll with a random number generator, such as a seeded one to reproduce rolls
+    pub fn roll_with<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<i64, RollError> {
+        Ok(self.roll_detailed_with(rng)?.value)
+    }
+    /// Roll and return every die rolled, in a tree with the same structure as the expression
+    pub fn roll_detailed(&mut self) -> Result<DiceRoll, RollError> {
+        self.roll_detailed_with(&mut rand::thread_rng())
+    }
+    /// Roll with a random number generator and return every die rolled
+    pub fn roll_detailed_with<R: Rng + ?Sized>(&mut self, rng: &mut R)
+            -> Result<DiceRoll, RollError> {
+        let result = self.expr.roll_log(&self.options, rng)?;
+        self.history.push(result.clone());
+        return Ok(result);
+    }
+    /// Roll with values for the variables in the expression, such as `@prof`
+    pub fn roll_with_context(&mut self, context: &HashMap<String, i64>)
+            -> Result<i64, RollError> {
+        Ok(self.roll_detailed_with_context(context, &mut rand::thread_rng())?.value)
+    }
+    /// Roll with values for the variables in the expression and a random number generator,
+    /// returning every die rolled
+    pub fn roll_detailed_with_context<R: Rng + ?Sized>(&mut self, context: &HashMap<String, i64>,
+            rng: &mut R) -> Result<DiceRoll, RollError> {
+        let result = self.expr.bind(context)?.roll_log(&self.options, rng)?;
+        self.history.push(result.clone());
+        return Ok(result);
+    }
+    /// Copy of the dice with every variable replaced by its value, without any history
+    pub fn bind(&self, context: &HashMap<String, i64>) -> Result<Self, DiceParseError> {
+        Ok(Self {
+            name: self.name.clone(),
+            expr: self.expr.bind(context)?,
+            history: vec![],
+            options: self.options,
+        })
+    }
+    /// Copy of the dice rolling the damage of a critical hit, without any history
+    pub fn crit(&self, mode: CritMode) -> Self {
+        Self {
+            name: self.name.clone(),
+            expr: self.expr.crit(mode),
+            history: vec![],
+            options: self.options,
+        }
+    }
+    /// Every past roll of these dice, oldest first
+    pub fn history(&self) -> &[DiceRoll] {
+        &self.history
     }
     pub fn log(&self, i: usize) -> String {
         self.history[self.history.len() - 1 - i].full_log()
     }
+    /// Successes less failures from the dice pools in a past roll, if it had any
+    pub fn successes(&self, i: usize) -> Option<i64> {
+        self.history[self.history.len() - 1 - i].successes()
+    }
+    /// Whether a past roll met the target of its comparison, if it had one
+    pub fn passed(&self, i: usize) -> Option<bool> {
+        self.history[self.history.len() - 1 - i].passed()
+    }
+    /// Outcomes of each die counted in the dice pools of a past roll
+    pub fn outcomes(&self, i: usize) -> Vec<Outcome> {
+        self.history[self.history.len() - 1 - i].outcomes()
+    }
+}
+impl Default for Dice {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl FromStr for Dice {
     type Err = DiceParseError;
 
     fn from_str(s: &str) -> Result<Self, <Dice as FromStr>::Err> {
-        let dice_set = match DiceSet::parse(s) {
-            Ok(d) => d,
-            Err(e) => return Err(e),
-        };
         Ok(Self {
             name: None,
-            set: dice_set,
+            expr: s.parse()?,
             history: vec![],
+            options: RollOptions::default(),
         })
     }
 }
+impl From<Expr> for Dice {
+    fn from(expr: Expr) -> Self {
+        Self {
+            name: None,
+            expr,
+            history: vec![],
+            options: RollOptions::default(),
+        }
+    }
+}
 impl std::fmt::Display for Dice {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.name {
-            Some(s) => write!(f, "{}: {}", s, self.set),
-            None => write!(f, "{}", self.set),
+            Some(s) => write!(f, "{}: {}", s, self.expr),
+            None => write!(f, "{}", self.expr),
         }
     }
 }
@@ -74,11 +223,12 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::partialeq_to_none, clippy::len_zero)]
     fn test_dice_new() {
         let d = Dice::new();
         assert!(d.name == None);
-        assert!(match d.set{
-            DiceSet::Const(n) => n == 0,
+        assert!(match d.expr {
+            Expr::Num(n) => n == 0,
             _ => false,
         });
         assert!(d.history.len() == 0);
@@ -97,18 +247,223 @@ mod tests {
         let mut d = Dice::from("3d4 - 1").unwrap();
         let mut sum: i64 = 0;
         for _ in 1..100 {
-            sum += d.roll();
+            sum += d.roll().unwrap();
         }
         assert!(sum <= 11*100);
         assert!(sum >= 2*100);
     }
     #[test]
+    fn test_dice_roll_with() {
+        use rand::SeedableRng;
+        let mut a = Dice::from("2d20adv + 3d6r1").unwrap();
+        let mut b = Dice::from("2d20adv + 3d6r1").unwrap();
+        let mut rng_a = rand::rngs::StdRng::seed_from_u64(42);
+        let mut rng_b = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..10 {
+            assert_eq!(a.roll_with(&mut rng_a).unwrap(), b.roll_with(&mut rng_b).unwrap());
+            assert_eq!(a.log(0), b.log(0));
+        }
+    }
+    #[test]
+    fn test_dice_roll_with_context() {
+        let mut d = Dice::from("2d1 + @str_mod + @prof").unwrap();
+        let mut context = HashMap::from([(String::from("str_mod"), 3)]);
+        assert_eq!(d.roll_with_context(&context),
+            Err(RollError::Parse(DiceParseError::UnboundVariable(String::from("prof")))));
+        assert!(d.history.is_empty());
+        context.insert(String::from("prof"), 2);
+        assert_eq!(d.roll_with_context(&context), Ok(7));
+        context.insert(String::from("prof"), -4);
+        assert_eq!(d.roll_with_context(&context), Ok(1));
+        assert_eq!(d.history().len(), 2);
+        assert_eq!(d.log(1), "| 1 1 ");
+        assert_eq!(d.to_string(), "2d1 + @str_mod + @prof");
+        let mut bound = d.bind(&context).unwrap();
+        assert_eq!(bound.to_string(), "2d1 + 3 + (-4)");
+        assert_eq!(bound.roll().unwrap(), 1);
+        assert_eq!(bound.mean(), Ok(1.0));
+        assert!(d.mean().is_err());
+    }
+    #[test]
+    fn test_dice_roll_err() {
+        let mut d = Dice::from("1d20/(1d1 - 1)").unwrap();
+        assert_eq!(d.roll(), Err(RollError::DivideByZero(String::from("1d20/(1d1 - 1)"))));
+        assert!(d.history().is_empty());
+        assert_eq!(d.mean(), Err(DistributionError::DivideByZero(String::from("1d20/(1d1 - 1)"))));
+        let mut d = Dice::from("10d1/@n").unwrap();
+        let context = HashMap::from([(String::from("n"), 4)]);
+        assert_eq!(d.roll_with_context(&context), Ok(2));
+        assert_eq!(d.roll(), Err(RollError::Parse(DiceParseError::UnboundVariable(String::from("n")))));
+        assert_eq!(d.bind(&context).unwrap().mean(), Ok(2.0));
+    }
+    #[test]
+    fn test_dice_roll_detailed() {
+        let mut d = Dice::from("-(1d4kh1)x3 + 2d1!").unwrap();
+        d.explode_depth(1);
+        let r = d.roll_detailed().unwrap();
+        assert_eq!(d.log(0), r.full_log());
+        let (lhs, rhs) = match &r.part {
+            RollPart::Binary(BinOp::Add, lhs, rhs) => (lhs, rhs),
+            _ => panic!(),
+        };
+        assert!(matches!(&lhs.part, RollPart::Binary(BinOp::Mul, neg, three)
+            if matches!(neg.part, RollPart::Neg(_)) && three.value == 3));
+        match &rhs.part {
+            RollPart::Dice(set) => {
+                assert_eq!(set.size, 1);
+                assert_eq!(set.dice.iter().map(|d| d.exploded).collect::<Vec<bool>>(),
+                    vec![false, true, false, true]);
+            },
+            _ => panic!(),
+        }
+        assert_eq!(rhs.value, 4);
+        assert_eq!(r.value, lhs.value + 4);
+        assert_eq!(r.sets().len(), 2);
+    }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_dice_serde() {
+        let mut d = Dice::from("2d(4) + 1d20kh1!").unwrap();
+        d.name("attack");
+        d.explode_depth(3);
+        d.roll().unwrap();
+        d.roll().unwrap();
+        let json = serde_json::to_string(&d).unwrap();
+        assert!(json.starts_with("{\"name\":\"attack\",\"expr\":\"2d(4) + 1d20kh1!\",\"history\":["));
+        let back: Dice = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.expr, d.expr);
+        assert_eq!(back.history, d.history);
+        assert_eq!(back.options.explode_depth, 3);
+        assert_eq!(back.log(1), d.log(1));
+        let back: Dice = serde_json::from_str("{\"name\":null,\"expr\":\"d6\",\"history\":[]}")
+            .unwrap();
+        assert_eq!(back.to_string(), "1d6");
+        assert!(serde_json::from_str::<Dice>("{\"name\":null,\"expr\":\"1dd\",\"history\":[]}")
+            .is_err());
+    }
+    #[test]
+    fn test_dice_explode_depth() {
+        let mut d = Dice::from("1d1!").unwrap();
+        assert_eq!(d.roll().unwrap(), 1 + EXPLODE_DEPTH as i64);
+        d.explode_depth(3);
+        assert_eq!(d.roll().unwrap(), 4);
+        assert_eq!(d.log(0), "| 1 !1 !1 !1 ");
+        let mut d = Dice::from("2d1!!").unwrap();
+        d.explode_depth(2);
+        assert_eq!(d.roll().unwrap(), 6);
+        assert_eq!(d.log(0), "| 1+1+1 1+1+1 ");
+    }
+    #[test]
+    fn test_dice_extra_roll_limit() {
+        let start = std::time::Instant::now();
+        let mut d = Dice::from("100d1!").unwrap();
+        assert_eq!(d.roll().unwrap(), 100 + EXTRA_ROLL_LIMIT as i64);
+        assert_eq!(d.history()[0].sets()[0].dice.len(), 100 + EXTRA_ROLL_LIMIT as usize);
+        let mut d = Dice::from("100d1!!").unwrap();
+        assert_eq!(d.roll().unwrap(), 100 + EXTRA_ROLL_LIMIT as i64);
+        let mut d = Dice::from("100d1rr1").unwrap();
+        assert_eq!(d.roll().unwrap(), 100);
+        assert_eq!(d.history()[0].sets()[0].dice.iter().map(|r| r.rerolled.len()).sum::<usize>(),
+            EXTRA_ROLL_LIMIT as usize);
+        // Compounded faces are added up past the largest face
+        let mut d = Dice::from("1d4294967295!!>0").unwrap();
+        let r = d.roll().unwrap();
+        let die = &d.history()[0].sets()[0].dice[0];
+        assert_eq!(die.compounded.len(), EXPLODE_DEPTH as usize);
+        assert_eq!(u64::try_from(r), Ok(die.value()));
+        assert!(r > i64::from(u32::MAX));
+        // Statistics agree with rolls up to the limit, and are not calculated past it
+        let mut d = Dice::from("10d1!").unwrap();
+        assert_eq!(d.max(), Ok(d.roll().unwrap()));
+        assert_eq!(d.mean(), Ok(1010.0));
+        assert!(Dice::from("100d1!").unwrap().mean().is_err());
+        assert!(start.elapsed().as_secs_f64() < 2.0);
+    }
+    #[test]
+    fn test_dice_successes() {
+        let mut d = Dice::from("(3d1>=1) + (2d1=1f1) + 1d6").unwrap();
+        let r = d.roll().unwrap();
+        assert!((6..=11).contains(&r));
+        assert_eq!(d.successes(0), Some(5));
+        assert_eq!(d.outcomes(0), vec![Outcome::Success; 5]);
+        let mut d = Dice::from("2d6").unwrap();
+        d.roll().unwrap();
+        assert_eq!(d.successes(0), None);
+        assert!(d.outcomes(0).is_empty());
+    }
+    #[test]
+    fn test_dice_crit_range() {
+        let mut d = Dice::from("20d20").unwrap();
+        d.crit_range(2);
+        let r = d.roll().unwrap();
+        assert!(d.history()[0].is_crit() || r == 20);
+        for die in &d.history()[0].sets()[0].dice {
+            assert_eq!(die.crit, if die.face == 1 { Crit::Fumble } else { Crit::Hit });
+        }
+        let mut d = Dice::from("1d20").unwrap();
+        d.crit_range(21);
+        d.roll().unwrap();
+        assert!(!d.history()[0].is_crit());
+    }
+    #[test]
+    fn test_dice_crit() {
+        let mut d = Dice::from("2d6 + 4").unwrap();
+        d.name("greatsword");
+        d.explode_depth(1);
+        d.roll().unwrap();
+        let c = d.crit(CritMode::default());
+        assert_eq!(c.to_string(), "greatsword: 4d6 + 4");
+        assert_eq!(c.options.explode_depth, 1);
+        assert!(c.history().is_empty());
+        assert_eq!((c.min(), c.max()), (Ok(8), Ok(28)));
+        let c = d.crit(CritMode::MaxPlusRoll);
+        assert_eq!((c.min(), c.max()), (Ok(18), Ok(28)));
+        assert_eq!(d.crit(CritMode::DoubleTotal).mean(), Ok(22.0));
+    }
+    #[test]
+    fn test_dice_passed() {
+        let mut d = Dice::from("1d20 + 5 >= @dc").unwrap();
+        let context = HashMap::from([(String::from("dc"), 5)]);
+        assert!(d.roll_with_context(&context).unwrap() >= 6);
+        assert_eq!(d.passed(0), Some(true));
+        assert_eq!(d.bind(&context).unwrap().mean(), Ok(15.5));
+        let mut d = Dice::from("1d20").unwrap();
+        d.roll().unwrap();
+        assert_eq!(d.passed(0), None);
+    }
+    #[test]
+    fn test_dice_distribution() {
+        let d = Dice::from("8d6").unwrap();
+        let dist = d.distribution().unwrap();
+        assert_eq!((dist.min(), dist.max()), (8, 48));
+        assert!(dist.at_least(30) > dist.at_least(31));
+        let mut d = Dice::from("1d2!").unwrap();
+        d.explode_depth(1);
+        assert_eq!(d.distribution().unwrap().max(), 4);
+        assert!(Dice::from("2d6!dl1").unwrap().distribution().is_err());
+    }
+    #[test]
+    fn test_dice_stats() {
+        let d = Dice::from("3d6 + 2").unwrap();
+        assert_eq!(d.mean().unwrap(), 12.5);
+        assert_eq!(d.variance().unwrap(), 8.75);
+        assert_eq!(d.std_dev().unwrap(), 8.75f64.sqrt());
+        assert_eq!((d.min().unwrap(), d.max().unwrap()), (5, 20));
+        assert_eq!(d.median().unwrap(), 12);
+        assert_eq!(d.percentile(90.0).unwrap(), 16);
+        let d = Dice::from("1d20adv").unwrap();
+        assert!((d.mean().unwrap() - 13.825).abs() < 1e-9);
+        assert_eq!((d.min().unwrap(), d.max().unwrap()), (1, 20));
+        assert!(Dice::from("2d6!dl1").unwrap().mean().is_err());
+    }
+    #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_dice_log() {
         let mut d = Dice::from("1d6 + 3").unwrap();
         let mut output = [0; 5];
         let mut i = 4;
         loop {
-            output[i] = d.roll();
+            output[i] = d.roll().unwrap();
             if i == 0 {
                 break;
             }
@@ -118,6 +473,8 @@ mod tests {
             let log = d.log(i);
             assert!(log == format!("| {} ", output[i] - 3));
         }
+        let history: Vec<i64> = d.history().iter().map(|r| r.value).collect();
+        assert_eq!(history, output.iter().rev().copied().collect::<Vec<i64>>());
     }
 
     // Blackbox
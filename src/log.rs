@@ -3,195 +3,625 @@
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
 use std::fmt;
+use crate::dice::{BinOp, CmpOp, Compare, Func};
 
 
-// Results from Dice
-#[derive(Debug)]
+/// Result of rolling an expression, a tree with the same structure as the expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DiceRoll {
-    rolled: i64,
-    log: Vec<RollLog>,
+    /// Result of this part of the expression
+    pub value: i64,
+    /// Rolls made for this part of the expression
+    pub part: RollPart,
 }
 impl DiceRoll {
     // Constructors
-    pub fn new(x: i64) -> DiceRoll {
-        DiceRoll { rolled: x, log: vec![] }
+    pub(crate) fn new(x: i64) -> DiceRoll {
+        DiceRoll { value: x, part: RollPart::Num }
+    }
+    pub(crate) fn set(number: DiceRoll, sides: DiceRoll, value: i64, log: &RollLog,
+            crit_range: u32) -> DiceRoll {
+        let mut dice = log.die_rolls();
+        // Only kept d20s crit, on a natural 1 or a roll in the crit range
+        if log.size() == 20 {
+            for d in dice.iter_mut().filter(|d| !d.dropped) {
+                if d.face == 1 {
+                    d.crit = Crit::Fumble;
+                } else if d.face >= crit_range {
+                    d.crit = Crit::Hit;
+                }
+            }
+        }
+        DiceRoll {
+            value,
+            part: RollPart::Dice(SetRoll {
+                number: Box::new(number),
+                sides: Box::new(sides),
+                size: log.size(),
+                dice,
+                successes: log.successes(),
+            }),
+        }
+    }
+    // Negate a result, with no result if it is too large
+    pub(crate) fn neg(x: DiceRoll) -> Option<DiceRoll> {
+        let value = x.value.checked_neg()?;
+        Some(DiceRoll { value, part: RollPart::Neg(Box::new(x)) })
+    }
+    pub(crate) fn group(x: DiceRoll) -> DiceRoll {
+        DiceRoll { value: x.value, part: RollPart::Group(Box::new(x)) }
+    }
+    // Combine two results with an operator, with no result for division by zero or overflow
+    pub(crate) fn binary(op: BinOp, lhs: DiceRoll, rhs: DiceRoll) -> Option<DiceRoll> {
+        let value = op.apply(lhs.value, rhs.value)?;
+        Some(DiceRoll { value, part: RollPart::Binary(op, Box::new(lhs), Box::new(rhs)) })
+    }
+    // Call a function on results, with no result for the wrong number of arguments
+    pub(crate) fn call(func: Func, args: Vec<DiceRoll>) -> Option<DiceRoll> {
+        let values: Vec<i64> = args.iter().map(|a| a.value).collect();
+        let value = func.apply(&values)?;
+        Some(DiceRoll { value, part: RollPart::Call(func, args) })
     }
-    pub fn new_roll(x: i64, roll: RollLog) -> DiceRoll {
-        DiceRoll { rolled: x, log: vec![roll] }
+    pub(crate) fn check(op: CmpOp, total: DiceRoll, target: DiceRoll) -> DiceRoll {
+        DiceRoll { value: total.value, part: RollPart::Check(op, Box::new(total), Box::new(target)) }
     }
 
-    // Accessors
-    pub fn num(&self) -> i64 {
-        self.rolled
+    /// Every set of dice rolled, in the order they were rolled
+    pub fn sets(&self) -> Vec<&SetRoll> {
+        let mut result = vec![];
+        self.collect_sets(&mut result);
+        return result;
     }
-    pub fn full_log(&self) -> String {
-        if self.log.len() == 1 {
-            format!("{}", self.log[0]);
+    fn collect_sets<'a>(&'a self, sets: &mut Vec<&'a SetRoll>) {
+        match &self.part {
+            RollPart::Num => (),
+            RollPart::Dice(set) => {
+                set.number.collect_sets(sets);
+                set.sides.collect_sets(sets);
+                sets.push(set);
+            },
+            RollPart::Neg(x) | RollPart::Group(x) => x.collect_sets(sets),
+            RollPart::Binary(_, lhs, rhs) | RollPart::Check(_, lhs, rhs) => {
+                lhs.collect_sets(sets);
+                rhs.collect_sets(sets);
+            },
+            RollPart::Call(_, args) => {
+                for arg in args {
+                    arg.collect_sets(sets);
+                }
+            },
         }
-        let mut result = String::from("");
-            //format!("| d{}: ");
-        if self.log.len() == 1 {
-                result.push_str(&format!("| {}", self.log[0]));
-        } else {
-            for l in &self.log {
-                result.push_str(&format!("| d{}: {}", l.size(), l));
-            }
+    }
+    /// Successes less failures from every dice pool rolled, if any were
+    pub fn successes(&self) -> Option<i64> {
+        self.sets().iter().filter_map(|s| s.successes).reduce(|a, b| a + b)
+    }
+    /// Whether the total met its target, if the expression was a comparison
+    pub fn passed(&self) -> Option<bool> {
+        match &self.part {
+            RollPart::Check(op, _, target) => {
+                Some(Compare::new(*op, target.value).matches(self.value))
+            },
+            _ => None,
         }
-        return result;
     }
-
-    // Mutators
-    pub fn join(&mut self, other: &DiceRoll) {
-        for l in &other.log {
-            self.log.push(l.clone());
+    /// Target the total was compared against, if the expression was a comparison
+    pub fn target(&self) -> Option<i64> {
+        match &self.part {
+            RollPart::Check(_, _, target) => Some(target.value),
+            _ => None,
         }
     }
-    pub fn add(&mut self, other: &DiceRoll) {
-        self.rolled += other.rolled;
-        self.join(&other);
+    /// Outcomes of the dice counted in every dice pool rolled
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        let mut result = vec![];
+        for set in self.sets().iter().filter(|s| s.successes.is_some()) {
+            for d in set.dice.iter().filter(|d| !d.dropped) {
+                result.push(d.outcome);
+            }
+        }
+        return result;
     }
-    pub fn sub(&mut self, other: &DiceRoll) {
-        self.rolled -= other.rolled;
-        self.join(&other);
+    /// Whether any d20 rolled a critical hit
+    pub fn is_crit(&self) -> bool {
+        self.sets().iter().any(|s| s.dice.iter().any(|d| d.crit == Crit::Hit))
     }
-    pub fn mult(&mut self, other: &DiceRoll) {
-        self.rolled *= other.rolled;
-        self.join(&other);
+    /// Whether any d20 rolled a natural 1
+    pub fn is_fumble(&self) -> bool {
+        self.sets().iter().any(|s| s.dice.iter().any(|d| d.crit == Crit::Fumble))
     }
-    pub fn mult_neg(&mut self, other: &DiceRoll) {
-        self.rolled *= -1;
-        self.mult(&other);
+    /// Log of every die rolled, with the size of each set when there is more than one
+    pub fn full_log(&self) -> String {
+        self.full_log_with(|d| d.to_string())
+    }
+    /// Log of every die rolled, writing each die with a function, such as to color it
+    pub fn full_log_with<F: Fn(&DieRoll) -> String>(&self, f: F) -> String {
+        let sets = self.sets();
+        let mut result = String::from("");
+        for set in &sets {
+            if sets.len() > 1 {
+                result.push_str(&format!("| d{}: ", set.size));
+            } else {
+                result.push_str("| ");
+            }
+            for d in &set.dice {
+                result.push_str(&format!("{} ", f(d)));
+            }
+        }
+        return result;
     }
 }
 impl fmt::Display for DiceRoll {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        return write!(f, "{}", self.rolled);
+        return write!(f, "{}", self.value);
+    }
+}
+
+
+/// Rolls made for part of an expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RollPart {
+    /// A constant number, with nothing rolled
+    Num,
+    /// A set of dice
+    Dice(SetRoll),
+    /// A negated expression
+    Neg(Box<DiceRoll>),
+    /// An expression in parentheses
+    Group(Box<DiceRoll>),
+    /// Two expressions combined by an operator
+    Binary(BinOp, Box<DiceRoll>, Box<DiceRoll>),
+    /// A function called on the results of its arguments
+    Call(Func, Vec<DiceRoll>),
+    /// A total compared against a target, resulting in the total
+    Check(CmpOp, Box<DiceRoll>, Box<DiceRoll>),
+}
+
+
+/// Rolls of a set of dice
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SetRoll {
+    /// Result of the quantity of dice
+    pub number: Box<DiceRoll>,
+    /// Result of the number of sides on each die
+    pub sides: Box<DiceRoll>,
+    /// Number of sides the dice were rolled with
+    pub size: u32,
+    /// Each die rolled, in the order they were rolled
+    pub dice: Vec<DieRoll>,
+    /// Successes less failures, if the dice were counted as a pool
+    pub successes: Option<i64>,
+}
+
+
+/// Rolls of a single die
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DieRoll {
+    /// Face rolled, after any rerolls
+    pub face: u32,
+    /// Faces rolled before rerolling, from first to last
+    pub rerolled: Vec<u32>,
+    /// Faces of extra rolls added to this die by compounding
+    pub compounded: Vec<u32>,
+    /// Whether the die was dropped from the result
+    pub dropped: bool,
+    /// Whether the die was rolled because another die exploded
+    pub exploded: bool,
+    /// Outcome if the die was counted in a dice pool
+    pub outcome: Outcome,
+    /// Critical hit or fumble, if the die was a kept d20
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub crit: Crit,
+}
+impl DieRoll {
+    /// Value of the die, including compounded rolls
+    pub fn value(&self) -> u64 {
+        self.compounded.iter().map(|c| u64::from(*c)).sum::<u64>() + u64::from(self.face)
+    }
+}
+impl fmt::Display for DieRoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Exploded dice are marked with '!', rerolled rolls joined with 'r', and compounded rolls
+        // joined with '+'
+        let mut text = String::from("");
+        if self.exploded {
+            text.push('!');
+        }
+        for r in &self.rerolled {
+            text.push_str(&format!("{}r", r));
+        }
+        text.push_str(&self.face.to_string());
+        for c in &self.compounded {
+            text.push_str(&format!("+{}", c));
+        }
+        // Successes are marked with 's' and failures with 'f'
+        match self.outcome {
+            Outcome::Success => text.push('s'),
+            Outcome::Failure => text.push('f'),
+            Outcome::None => (),
+        }
+        if self.dropped {
+            return write!(f, "[{}]", text);
+        }
+        return write!(f, "{}", text);
     }
 }
 
 
-// Log of rolls preformed
+// Log of rolls preformed, including those dropped from the result
+//
+// Links are also indexed by the roll they point to as they are logged, so finding the rolls
+// compounded into a die or whether a roll was rerolled does not search every link.
 #[derive(Debug)]
 pub struct RollLog {
     size: u32,
     rolls: Vec<u32>,
+    dropped: Vec<bool>,
+    links: Vec<Link>,
+    outcomes: Vec<Outcome>,
+    pool: bool,
+    // Rolls compounded into each roll, and whether each roll was replaced by a reroll
+    compounded: Vec<Vec<usize>>,
+    rerolled: Vec<bool>,
 }
 impl RollLog {
     pub fn new(s: u32) -> Self {
-        Self { size: s, rolls: vec![] }
+        Self {
+            size: s,
+            rolls: vec![],
+            dropped: vec![],
+            links: vec![],
+            outcomes: vec![],
+            pool: false,
+            compounded: vec![],
+            rerolled: vec![],
+        }
     }
     pub fn size(&self) -> u32 {
         self.size
     }
+    pub fn len(&self) -> usize {
+        self.rolls.len()
+    }
+    // Indices of separate dice, excluding rolls compounded into another die or rerolled
+    pub fn dice(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(|i| {
+            !matches!(self.links[*i], Link::Compound(_)) && !self.rerolled[*i]
+        })
+    }
+    // Value of a die, including rolls compounded into it, which cannot overflow since the extra
+    // rolls of a set are limited
+    pub fn value(&self, i: usize) -> u64 {
+        self.compounded[i].iter().map(|j| u64::from(self.rolls[*j])).sum::<u64>()
+            + u64::from(self.rolls[i])
+    }
+    pub fn is_dropped(&self, i: usize) -> bool {
+        self.dropped[i]
+    }
+    // Values of dice still counted in the result
+    pub fn kept(&self) -> impl Iterator<Item = u64> + '_ {
+        self.dice().filter(|i| !self.dropped[*i]).map(|i| self.value(i))
+    }
+    // Successes less failures, if the dice were counted as a pool
+    pub fn successes(&self) -> Option<i64> {
+        if !self.pool {
+            return None;
+        }
+        return Some(self.outcomes.iter().map(|o| match o {
+            Outcome::Success => 1,
+            Outcome::Failure => -1,
+            Outcome::None => 0,
+        }).sum());
+    }
+    // Rolls of each separate die
+    pub fn die_rolls(&self) -> Vec<DieRoll> {
+        let mut result = vec![];
+        for i in self.dice() {
+            // Rolls replaced by rerolling this die, from the last roll back to the first
+            let mut first = i;
+            let mut rerolled = vec![];
+            while let Link::Reroll(prev) = self.links[first] {
+                rerolled.push(self.rolls[prev]);
+                first = prev;
+            }
+            rerolled.reverse();
+            result.push(DieRoll {
+                face: self.rolls[i],
+                rerolled,
+                compounded: self.compounded[i].iter().map(|j| self.rolls[*j]).collect(),
+                dropped: self.dropped[i],
+                exploded: matches!(self.links[first], Link::Explode(_)),
+                outcome: self.outcomes[i],
+                crit: Crit::None,
+            });
+        }
+        return result;
+    }
     pub fn log(&mut self, s: u32) {
+        self.log_linked(s, Link::None);
+    }
+    pub fn log_linked(&mut self, s: u32, link: Link) {
         if s > self.size {
             panic!("Invalid number logged");
         }
+        let n = self.len();
+        match link {
+            Link::Compound(i) => self.compounded[i].push(n),
+            Link::Reroll(i) => self.rerolled[i] = true,
+            _ => (),
+        }
         self.rolls.push(s);
+        self.dropped.push(false);
+        self.links.push(link);
+        self.outcomes.push(Outcome::None);
+        self.compounded.push(vec![]);
+        self.rerolled.push(false);
+    }
+    pub fn drop(&mut self, i: usize) {
+        self.dropped[i] = true;
+    }
+    pub fn drop_all(&mut self) {
+        for d in self.dropped.iter_mut() {
+            *d = true;
+        }
+        for o in self.outcomes.iter_mut() {
+            *o = Outcome::None;
+        }
+    }
+    // Count kept dice as a pool of successes and failures
+    pub fn count(&mut self, success: Compare, failure: Option<Compare>) {
+        self.pool = true;
+        let kept: Vec<usize> = self.dice().filter(|i| !self.dropped[*i]).collect();
+        for i in kept {
+            let value = i64::try_from(self.value(i)).unwrap_or(i64::MAX);
+            if success.matches(value) {
+                self.outcomes[i] = Outcome::Success;
+            } else if failure.is_some_and(|f| f.matches(value)) {
+                self.outcomes[i] = Outcome::Failure;
+            }
+        }
+    }
+    // Add rolls from another log of the same dice
+    pub fn append(&mut self, other: &RollLog) {
+        let offset = self.len();
+        self.rolls.extend(&other.rolls);
+        self.dropped.extend(&other.dropped);
+        self.outcomes.extend(&other.outcomes);
+        self.rerolled.extend(&other.rerolled);
+        for c in &other.compounded {
+            self.compounded.push(c.iter().map(|j| j + offset).collect());
+        }
+        self.pool |= other.pool;
+        for l in &other.links {
+            self.links.push(match l {
+                Link::None => Link::None,
+                Link::Explode(i) => Link::Explode(i + offset),
+                Link::Compound(i) => Link::Compound(i + offset),
+                Link::Reroll(i) => Link::Reroll(i + offset),
+            });
+        }
     }
 }
 impl fmt::Display for RollLog {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = String::from("");
-        for r in &self.rolls {
-            result.push_str(&format!("{} ", r));
+        for d in self.die_rolls() {
+            write!(f, "{} ", d)?;
         }
-        return write!(f, "{}", result);
+        return Ok(());
     }
 }
+
 impl Clone for RollLog {
     fn clone(&self) -> Self {
         let mut fin = Self::new(self.size);
         for l in &self.rolls {
             fin.rolls.push(*l);
         }
+        for d in &self.dropped {
+            fin.dropped.push(*d);
+        }
+        for l in &self.links {
+            fin.links.push(*l);
+        }
+        for o in &self.outcomes {
+            fin.outcomes.push(*o);
+        }
+        for c in &self.compounded {
+            fin.compounded.push(c.clone());
+        }
+        for r in &self.rerolled {
+            fin.rerolled.push(*r);
+        }
+        fin.pool = self.pool;
         return fin;
     }
 }
 
 
+// Connection from an extra roll to the die which caused it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Link {
+    None,
+    // Separate die rolled when another exploded
+    Explode(usize),
+    // Roll added to the value of another die
+    Compound(usize),
+    // Roll replacing another roll of the same die
+    Reroll(usize),
+}
+
+
+/// Outcome of a die counted in a dice pool
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    /// Not counted as a success or failure
+    None,
+    /// Matched the success target
+    Success,
+    /// Matched the failure target
+    Failure,
+}
+
+
+/// Critical result of a natural roll on a d20
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Crit {
+    /// Not a critical roll
+    #[default]
+    None,
+    /// Rolled within the crit range, a natural 20 by default
+    Hit,
+    /// Rolled a natural 1
+    Fumble,
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dice::CmpOp;
 
     // DiceRoll
+    fn set_roll(size: u32, rolls: &[u32]) -> DiceRoll {
+        let mut rl = RollLog::new(size);
+        for r in rolls {
+            rl.log(*r);
+        }
+        let sum = rolls.iter().sum::<u32>().into();
+        DiceRoll::set(DiceRoll::new(rolls.len() as i64), DiceRoll::new(size.into()), sum, &rl, 20)
+    }
     #[test]
     fn test_dice_roll_new() {
         let dr = DiceRoll::new(13);
-        assert!(dr.num() == 13);
+        assert!(dr.value == 13);
+        assert!(dr.part == RollPart::Num);
     }
     #[test]
-    fn test_dice_roll_new_roll() {
-        let mut rl = RollLog::new(4);
-        rl.log(2);
-        let dr = DiceRoll::new_roll(13, rl);
-        assert!(dr.num() == 13);
-        assert!(dr.log[0].rolls[0] == 2);
+    fn test_dice_roll_set() {
+        let dr = set_roll(4, &[2, 3]);
+        assert!(dr.value == 5);
+        let set = match dr.part {
+            RollPart::Dice(set) => set,
+            _ => panic!(),
+        };
+        assert_eq!(set.size, 4);
+        assert_eq!(set.dice.iter().map(|d| d.face).collect::<Vec<u32>>(), vec![2, 3]);
+        assert_eq!(set.number.value, 2);
+        assert_eq!(set.successes, None);
     }
     #[test]
     fn test_dice_roll_full_log() {
-        let mut rl4 = RollLog::new(4);
-        rl4.log(2);
-        let dr4 = DiceRoll::new_roll(2, rl4);
-        let mut rl8 = RollLog::new(8);
-        rl8.log(7);
-        rl8.log(3);
-        let mut dr8 = DiceRoll::new_roll(10, rl8);
-        dr8.join(&dr4);
-        assert!("| d8: 7 3 | d4: 2 " == &dr8.full_log());
-    }
-    #[test]
-    fn test_dice_roll_join() {
-        let mut rl4 = RollLog::new(3);
-        rl4.log(2);
-        rl4.log(1);
-        rl4.log(3);
-        let dr3 = DiceRoll::new_roll(6, rl4);
-        let mut rl12 = RollLog::new(12);
-        rl12.log(10);
-        let mut dr12 = DiceRoll::new_roll(10, rl12);
-        dr12.join(&dr3);
-        assert!(dr12.log[0].rolls[0] == 10);
-        assert!(dr12.log[1].rolls[0] == 2);
-        assert!(dr12.log[1].rolls[1] == 1);
-        assert!(dr12.log[1].rolls[2] == 3);
-    }
-    #[test]
-    fn test_dice_roll_add() {
-        let mut dr7 = DiceRoll::new(7);
-        let dr1 = DiceRoll::new(1);
-        dr7.add(&dr1);
-        assert!(dr7.num() == 8);
-    }
-    #[test]
-    fn test_dice_roll_sub() {
-        let mut dr9 = DiceRoll::new(9);
-        let dr4 = DiceRoll::new(4);
-        dr9.sub(&dr4);
-        assert!(dr9.num() == 5);
-    }
-    #[test]
-    fn test_dice_roll_mult() {
-        let mut dr8 = DiceRoll::new(8);
-        let dr2 = DiceRoll::new(2);
-        dr8.mult(&dr2);
-        assert!(dr8.num() == 16);
-    }
-    #[test]
-    fn test_dice_roll_mult_neg() {
-        let mut dr8 = DiceRoll::new(12);
-        let dr2 = DiceRoll::new(3);
-        dr8.mult_neg(&dr2);
-        assert!(dr8.num() == -36);
+        let dr = DiceRoll::binary(BinOp::Add, set_roll(8, &[7, 3]), set_roll(4, &[2])).unwrap();
+        assert!("| d8: 7 3 | d4: 2 " == &dr.full_log());
+        assert!("| 7 3 " == &set_roll(8, &[7, 3]).full_log());
+    }
+    #[test]
+    fn test_dice_roll_sets() {
+        let inner = DiceRoll::group(set_roll(3, &[2, 1, 3]));
+        let dr = DiceRoll::binary(BinOp::Mul, DiceRoll::neg(set_roll(12, &[10])).unwrap(), inner).unwrap();
+        let sizes: Vec<u32> = dr.sets().iter().map(|s| s.size).collect();
+        assert_eq!(sizes, vec![12, 3]);
+    }
+    #[test]
+    fn test_dice_roll_binary() {
+        let dr = DiceRoll::binary(BinOp::Add, DiceRoll::new(7), DiceRoll::new(1)).unwrap();
+        assert!(dr.value == 8);
+        let dr = DiceRoll::binary(BinOp::Sub, DiceRoll::new(9), DiceRoll::new(4)).unwrap();
+        assert!(dr.value == 5);
+        let dr = DiceRoll::binary(BinOp::Mul, DiceRoll::new(8), DiceRoll::new(2)).unwrap();
+        assert!(dr.value == 16);
+        let dr = DiceRoll::binary(BinOp::Div, DiceRoll::new(-7), DiceRoll::new(2)).unwrap();
+        assert!(dr.value == -4);
+        assert!(DiceRoll::binary(BinOp::DivUp, DiceRoll::new(7), DiceRoll::new(0)).is_none());
+    }
+    #[test]
+    fn test_dice_roll_call() {
+        let dr = DiceRoll::call(Func::Max, vec![DiceRoll::new(1), set_roll(4, &[3])]).unwrap();
+        assert!(dr.value == 3);
+        assert_eq!(dr.sets().len(), 1);
+        let dr = DiceRoll::call(Func::Clamp, vec![DiceRoll::new(-2), DiceRoll::new(0),
+            DiceRoll::new(5)]).unwrap();
+        assert!(dr.value == 0);
+        assert!(DiceRoll::call(Func::Abs, vec![]).is_none());
+    }
+    #[test]
+    fn test_dice_roll_neg() {
+        let dr = DiceRoll::neg(DiceRoll::group(DiceRoll::new(3))).unwrap();
+        assert!(dr.value == -3);
+    }
+    #[test]
+    fn test_dice_roll_check() {
+        let dr = DiceRoll::check(CmpOp::Ge, set_roll(20, &[14]), DiceRoll::new(15));
+        assert_eq!((dr.value, dr.target(), dr.passed()), (14, Some(15), Some(false)));
+        let dr = DiceRoll::check(CmpOp::Le, set_roll(20, &[14]), DiceRoll::new(15));
+        assert_eq!(dr.passed(), Some(true));
+        assert_eq!(dr.sets().len(), 1);
+        assert_eq!(DiceRoll::new(3).passed(), None);
+        assert_eq!(DiceRoll::new(3).target(), None);
+    }
+    #[test]
+    fn test_dice_roll_crit() {
+        let mut rl = RollLog::new(20);
+        for r in [20, 19, 1, 18, 20] {
+            rl.log(r);
+        }
+        rl.drop(4);
+        let dr = DiceRoll::set(DiceRoll::new(5), DiceRoll::new(20), 58, &rl, 19);
+        let crits: Vec<Crit> = dr.sets()[0].dice.iter().map(|d| d.crit).collect();
+        assert_eq!(crits, vec![Crit::Hit, Crit::Hit, Crit::Fumble, Crit::None, Crit::None]);
+        assert!(dr.is_crit() && dr.is_fumble());
+        assert_eq!(dr.full_log_with(|d| format!("{:?}", d.crit)), "| Hit Hit Fumble None None ");
+        let dr = set_roll(20, &[19, 2]);
+        assert!(!dr.is_crit() && !dr.is_fumble());
+        let dr = set_roll(6, &[6, 1]);
+        assert!(!dr.is_crit() && !dr.is_fumble());
     }
     #[test]
     fn test_dice_roll_fmt() {
         let dr = DiceRoll::new(5);
         assert!("5" == format!("{}", dr));
     }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_dice_roll_serde() {
+        let dr = DiceRoll::binary(BinOp::Sub, set_roll(6, &[4]), DiceRoll::new(1)).unwrap();
+        let json = serde_json::to_string(&dr).unwrap();
+        assert_eq!(json, concat!("{\"value\":3,\"part\":{\"Binary\":[\"Sub\",",
+            "{\"value\":4,\"part\":{\"Dice\":{\"number\":{\"value\":1,\"part\":\"Num\"},",
+            "\"sides\":{\"value\":6,\"part\":\"Num\"},\"size\":6,\"dice\":[{\"face\":4,",
+            "\"rerolled\":[],\"compounded\":[],\"dropped\":false,\"exploded\":false,",
+            "\"outcome\":\"None\",\"crit\":\"None\"}],\"successes\":null}}},",
+            "{\"value\":1,\"part\":\"Num\"}]}}"));
+        assert_eq!(serde_json::from_str::<DiceRoll>(&json).unwrap(), dr);
+        let old = json.replace(",\"crit\":\"None\"", "");
+        assert_eq!(serde_json::from_str::<DiceRoll>(&old).unwrap(), dr);
+    }
+    #[test]
+    fn test_die_roll() {
+        let mut rl = RollLog::new(6);
+        rl.log(1);
+        rl.log_linked(6, Link::Reroll(0));
+        rl.log_linked(3, Link::Compound(1));
+        rl.log_linked(2, Link::Explode(1));
+        rl.drop(3);
+        let dice = rl.die_rolls();
+        assert_eq!(dice.len(), 2);
+        assert_eq!(dice[0], DieRoll { face: 6, rerolled: vec![1], compounded: vec![3],
+            dropped: false, exploded: false, outcome: Outcome::None, crit: Crit::None });
+        assert_eq!(dice[0].value(), 9);
+        assert!(dice[1].exploded && dice[1].dropped);
+        assert_eq!(format!("{} {}", dice[0], dice[1]), "1r6+3 [!2]");
+    }
 
     // RollLog
     #[test]
+    #[allow(clippy::len_zero)]
     fn test_roll_log_new() {
         let rl = RollLog::new(7);
         assert!(rl.size == 7);
@@ -229,13 +659,93 @@ mod tests {
         assert_eq!("2 5 ", &format!("{}", rl));
     }
     #[test]
+    fn test_roll_log_drop() {
+        let mut rl = RollLog::new(6);
+        rl.log(2);
+        rl.log(5);
+        rl.log(1);
+        rl.drop(2);
+        assert!(rl.is_dropped(2));
+        assert!(!rl.is_dropped(0));
+        assert_eq!(rl.kept().collect::<Vec<u64>>(), vec![2, 5]);
+        assert_eq!("2 5 [1] ", &format!("{}", rl));
+    }
+    #[test]
+    fn test_roll_log_append() {
+        let mut rl = RollLog::new(20);
+        rl.log(4);
+        let mut other = RollLog::new(20);
+        other.log(17);
+        rl.drop_all();
+        rl.append(&other);
+        assert_eq!(rl.len(), 2);
+        assert_eq!("[4] 17 ", &format!("{}", rl));
+    }
+    #[test]
+    fn test_roll_log_links() {
+        let mut rl = RollLog::new(6);
+        rl.log(6);
+        rl.log_linked(6, Link::Explode(0));
+        rl.log_linked(2, Link::Explode(1));
+        rl.log(6);
+        rl.log_linked(6, Link::Compound(3));
+        rl.log_linked(1, Link::Compound(3));
+        assert_eq!(rl.dice().collect::<Vec<usize>>(), vec![0, 1, 2, 3]);
+        assert_eq!(rl.value(3), 13);
+        rl.drop(1);
+        assert_eq!(rl.kept().collect::<Vec<u64>>(), vec![6, 2, 13]);
+        assert_eq!("6 [!6] !2 6+6+1 ", &format!("{}", rl));
+    }
+    #[test]
+    fn test_roll_log_reroll() {
+        let mut rl = RollLog::new(6);
+        rl.log(1);
+        rl.log_linked(2, Link::Reroll(0));
+        rl.log_linked(5, Link::Reroll(1));
+        rl.log(6);
+        rl.log_linked(4, Link::Explode(3));
+        rl.log_linked(1, Link::Reroll(4));
+        assert_eq!(rl.dice().collect::<Vec<usize>>(), vec![2, 3, 5]);
+        assert_eq!(rl.kept().collect::<Vec<u64>>(), vec![5, 6, 1]);
+        assert_eq!("1r2r5 6 !4r1 ", &format!("{}", rl));
+    }
+    #[test]
+    fn test_roll_log_count() {
+        let mut rl = RollLog::new(10);
+        for r in [8, 1, 10, 5, 2] {
+            rl.log(r);
+        }
+        assert_eq!(rl.successes(), None);
+        rl.drop(4);
+        rl.count(Compare::new(CmpOp::Ge, 8), Some(Compare::new(CmpOp::Le, 2)));
+        assert_eq!(rl.successes(), Some(1));
+        assert_eq!("8s 1f 10s 5 [2] ", &format!("{}", rl));
+        let dr = DiceRoll::set(DiceRoll::new(5), DiceRoll::new(10), 1, &rl, 20);
+        assert_eq!(dr.successes(), Some(1));
+        assert_eq!(dr.outcomes(), vec![Outcome::Success, Outcome::Failure, Outcome::Success,
+            Outcome::None]);
+    }
+    #[test]
+    fn test_roll_log_append_links() {
+        let mut rl = RollLog::new(4);
+        rl.log(1);
+        let mut other = RollLog::new(4);
+        other.log(4);
+        other.log_linked(3, Link::Compound(0));
+        rl.append(&other);
+        assert_eq!(rl.value(1), 7);
+        assert_eq!("1 4+3 ", &format!("{}", rl));
+    }
+    #[test]
     fn test_roll_log_clone() {
         let mut rl = RollLog::new(20);
         rl.log(3);
         rl.log(1);
         rl.log(20);
         rl.log(19);
+        rl.drop(1);
         let new_rl = rl.clone();
+        assert!(new_rl.is_dropped(1));
         assert!(rl.size == new_rl.size);
         assert!(rl.rolls.len() == new_rl.rolls.len());
         for i in 0..3 {
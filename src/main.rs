@@ -2,10 +2,19 @@
 //   URL: https://github.com/pennbauman/dndice-rs
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::process;
 use colored::*;
-use dndice::{Dice, Scores};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use dndice::{Ability, Aliases, Assignment, Class, Constraints, Crit, CritMode, Dice,
+    DiceParseError, DiceRoll, DistributionError, Outcome, PointBuy, RollError, Scores, CRIT_RANGE};
 
 
 // Print error well formatted
@@ -24,6 +33,10 @@ macro_rules! err {
 }
 
 
+// Commands which cannot be used as alias names
+const COMMANDS: [&str; 5] = ["dice", "stats", "analyze", "repl", "alias"];
+
+
 // Print help text
 fn print_help() {
     println!("Usage: dndice [command] [dice] [options]");
@@ -34,11 +47,41 @@ fn print_help() {
     println!("    std, standard       Use the standard 5th edition statistics array");
     println!("    1d20                Roll 1d20 for each score");
     println!("    4d6                 Roll 4d6 and sum the largest 3 for each score");
+    println!("    pointbuy [scores]   Buy the six scores provided with 27 points, from 8 to 15");
+    println!("    [dice]              Roll the provided dice for each score, such as '2d6+6'");
+    println!("  analyze [dice]      Print the mean, spread, and percentiles of the provided dice");
+    println!("  repl                Roll dice interactively, reading an expression from each line");
+    println!("  alias [command]     Manage aliases, which are rolled by using their name as dice");
+    println!("    list                List every alias, used if no alias command is provided");
+    println!("    add [name] [dice]   Add an alias for the provided dice");
+    println!("    remove [name]       Remove an alias");
     println!();
     println!("Dice Format:");
-    println!("  Each expression uses dice sets, numbers, and the '+', '-', and '*' operators");
+    println!("  Each expression uses dice sets, numbers, and the '+', '-', '*', and '/' operators");
+    println!("  Division rounds down, or '/^' rounds up and '/~' rounds to the nearest number");
     println!("  Dice sets use '#d#' where the '#'s indicate dice quantity and size respectively.");
     println!("  Dice are rolled individually and their results summed and combined by operators");
+    println!("  Parentheses group parts of an expression, including dice quantities and sizes");
+    println!("  Dice sets may end with modifiers to keep or drop some of the dice rolled:");
+    println!("    kh#, kl#            Keep the highest or lowest # dice");
+    println!("    dh#, dl#            Drop the highest or lowest # dice");
+    println!("    adv, dis            Roll twice and use the higher or lower result");
+    println!("    !, !>#              Roll another die for each highest roll, or roll above #");
+    println!("    !!, !!>#            Explode dice adding the extra rolls to the first die");
+    println!("    r#, ro<#            Reroll dice once on a roll of #, or a roll below #");
+    println!("    rr#, rr<#           Reroll dice until they do not roll #, or a roll below #");
+    println!("    >#, >=#, =#         Count dice rolling above # as successes instead of summing");
+    println!("    f#, f<#             Subtract dice rolling # or below # from the successes");
+    println!("  Starting with 'adv' or 'dis' rolls every d20 with advantage or disadvantage");
+    println!("  Variables are written '@name' and given values with '--set'");
+    println!("  Functions are called with arguments in parentheses, separated by commas:");
+    println!("    min, max            Lowest or highest of two or more values");
+    println!("    abs                 Value without its sign");
+    println!("    floor, ceil         Value with a division in it rounded down or up");
+    println!("    clamp               First value limited to between the second and third");
+    println!("  A comparison like ' >= 15' after a space checks the total against a DC, exiting");
+    println!("    with status 2 if any roll fails");
+    println!("  The name of an alias may be used in place of dice, rolling the alias's dice");
     println!("  A '+' or '-' at the beginning indicates 1d20 will be added to the result");
     println!();
     println!("Options:");
@@ -46,10 +89,499 @@ fn print_help() {
     println!("  --version           Print the version number");
     println!("  --number, -n [num]  Repeat command the provided number of times");
     println!("  --quiet, -q         Print only essential information from command");
+    println!("  --interactive, -i   Start the interactive mode, the same as 'repl'");
+    println!("  --adv, --dis        Roll with advantage or disadvantage");
+    println!("  --seed [num]        Seed the random rolls to reproduce results");
+    println!("  --crit-range [num]  Lowest d20 roll which is a critical hit, 20 by default");
+    println!("  --crit              Roll the damage of a critical hit, doubling the dice rolled");
+    println!("  --crit-mode [mode]  Roll critical damage with 'double' dice, the 'max' of the dice");
+    println!("                        plus a roll, or double the 'total'");
+    println!("  --min-total [num]   Reroll statistics until their total is at least #");
+    println!("  --min-modifiers [num]");
+    println!("                      Reroll statistics until their modifiers total at least #");
+    println!("  --min-highest [num] Reroll statistics until one is at least #");
+    println!("  --max-below [count],[num]");
+    println!("                      Reroll statistics until at most count are below #");
+    println!("  --attempts [num]    Most statistics rolled to meet constraints, 100 by default");
+    println!("  --class [class]     Assign statistics to abilities by importance to a class");
+    println!("  --order [abilities] Assign statistics to abilities in order, like 'str,con,...'");
+    println!("  --bonus [ability]+[num]");
+    println!("                      Add a bonus to an ability's statistic, like 'dex+2'");
+    println!("  --set [name]=[num]  Set the value of a variable used in dice as '@name'");
+    println!("  --format [format]   Print results as 'human' readable text or 'json' lines");
     println!();
 }
 
 
+// Print help text for interactive mode
+fn print_repl_help() {
+    println!("Enter dice to roll them, or one of the following commands:");
+    println!("  !!                  Roll the last dice again");
+    println!("  :stats [method]     Generate a set of six statistics with the provided method");
+    println!("  :analyze [dice]     Print statistics of the provided dice, or the last dice");
+    println!("  :history            Print every roll of the last dice");
+    println!("  :set [name]=[num]   Set the value of a variable, or list variables if none is given");
+    println!("  :help               Print this help menu");
+    println!("  :quit               Exit, also done by 'exit' or the end of input");
+}
+
+
+// Path of the config file holding aliases
+fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    return Some(dir.join("dndice").join("config.toml"));
+}
+// Read aliases from the config file, exiting on errors
+fn read_aliases() -> Aliases {
+    let path = match config_path() {
+        Some(p) => p,
+        None => return Aliases::new(),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Aliases::new(),
+        Err(e) => {
+            err!(format!("Cannot read config, {}", e), path.display());
+        },
+    };
+    match text.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            err!(format!("{} in config", e), path.display());
+        },
+    }
+}
+// Write aliases to the config file, exiting on errors
+fn write_aliases(aliases: &Aliases) {
+    let path = match config_path() {
+        Some(p) => p,
+        None => {
+            err!("No config directory found");
+        },
+    };
+    let result = fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, aliases.to_string()));
+    if let Err(e) = result {
+        err!(format!("Cannot write config, {}", e), path.display());
+    }
+}
+
+
+// Build dice text from command arguments, expanding an alias and applying advantage or
+// disadvantage, with the name of any alias used
+fn dice_text(args: &[String], roll_mode: &str) -> (String, Option<String>) {
+    // Concatinate dice string, keeping spaces which separate comparisons from dice
+    let mut dice_text = args.iter().flat_map(|a| a.split_whitespace()).collect::<Vec<&str>>()
+        .join(" ");
+    let mut name = None;
+    if Aliases::valid_name(&dice_text) {
+        if let Some(text) = read_aliases().get(&dice_text) {
+            name = Some(dice_text);
+            dice_text = text.to_string();
+        }
+    }
+    if dice_text.starts_with('+') || dice_text.starts_with('-') {
+        dice_text = format!("1d20{}{}", roll_mode, dice_text);
+    } else if !roll_mode.is_empty() {
+        dice_text = format!("{} {}", roll_mode, dice_text);
+    }
+    return (dice_text, name);
+}
+// Parse abilities separated by commas or spaces, in the order scores are assigned to them
+fn parse_order(text: &str) -> Option<Assignment> {
+    let order = text.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty())
+        .map(Ability::from_name).collect::<Option<Vec<Ability>>>()?;
+    return Assignment::new(&order).ok();
+}
+// Parse an ability's bonus from text formatted 'ability+num' or 'ability-num'
+fn parse_bonus(text: &str) -> Option<(Ability, i8)> {
+    let i = text.find(['+', '-'])?;
+    let ability = Ability::from_name(&text[..i])?;
+    let amount = text[i..].strip_prefix('+').unwrap_or(&text[i..]).parse().ok()?;
+    return Some((ability, amount));
+}
+// Parse a variable's name and value from text formatted 'name=value' or '@name=value'
+fn parse_variable(text: &str) -> Option<(String, i64)> {
+    let (name, value) = text.split_once('=')?;
+    let name = name.trim();
+    let name = name.strip_prefix('@').unwrap_or(name);
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    return Some((name.to_string(), value.trim().parse().ok()?));
+}
+// Parse dice from command arguments, named after any alias used, and changed to roll critical
+// damage if a crit mode is given
+fn read_dice(args: &[String], roll_mode: &str, crit_range: u32, crit: Option<CritMode>)
+        -> Result<(String, Dice), DiceParseError> {
+    let (text, name) = dice_text(args, roll_mode);
+    let mut dice = Dice::from(&text)?;
+    if let Some(n) = name {
+        dice.name(n);
+    }
+    dice.crit_range(crit_range);
+    if let Some(mode) = crit {
+        dice = dice.crit(mode);
+    }
+    return Ok((text, dice));
+}
+// Parse dice, exiting on errors
+fn parse_dice(args: &[String], roll_mode: &str, crit_range: u32, crit: Option<CritMode>)
+        -> (String, Dice) {
+    match read_dice(args, roll_mode, crit_range, crit) {
+        Ok(d) => d,
+        Err(e) => {
+            err!(e);
+        },
+    }
+}
+
+
+// Quote text as a JSON string
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    return result;
+}
+// Write a list of JSON values
+fn json_list<T: ToString>(items: impl IntoIterator<Item = T>) -> String {
+    let items: Vec<String> = items.into_iter().map(|i| i.to_string()).collect();
+    return format!("[{}]", items.join(","));
+}
+// Write an optional number as JSON
+fn json_option<T: ToString>(item: Option<T>) -> String {
+    match item {
+        Some(i) => i.to_string(),
+        None => String::from("null"),
+    }
+}
+// Write a roll of dice as a JSON object, with each set of dice rolled
+fn json_roll(index: u16, text: &str, dice: &Dice, roll: &DiceRoll) -> String {
+    let mut sets = vec![];
+    for set in roll.sets() {
+        let mut dice = vec![];
+        for d in &set.dice {
+            let outcome = match d.outcome {
+                Outcome::Success => json_string("success"),
+                Outcome::Failure => json_string("failure"),
+                Outcome::None => String::from("null"),
+            };
+            let crit = match d.crit {
+                Crit::Hit => json_string("hit"),
+                Crit::Fumble => json_string("fumble"),
+                Crit::None => String::from("null"),
+            };
+            dice.push(format!("{{\"face\":{},\"value\":{},\"rerolled\":{},\"compounded\":{},\
+                \"dropped\":{},\"exploded\":{},\"outcome\":{},\"crit\":{}}}", d.face, d.value(),
+                json_list(&d.rerolled), json_list(&d.compounded), d.dropped, d.exploded, outcome,
+                crit));
+        }
+        sets.push(format!("{{\"size\":{},\"successes\":{},\"dice\":{}}}", set.size,
+            json_option(set.successes), json_list(dice)));
+    }
+    return format!("{{\"index\":{},\"expression\":{},\"canonical\":{},\"total\":{},\
+        \"target\":{},\"passed\":{},\"crit\":{},\"fumble\":{},\"successes\":{},\"sets\":{}}}",
+        index, json_string(text), json_string(&dice.expr().to_string()), roll.value,
+        json_option(roll.target()), json_option(roll.passed()), roll.is_crit(), roll.is_fumble(),
+        json_option(roll.successes()), json_list(sets));
+}
+
+
+// Print statistics calculated for dice
+fn print_analysis(text: &str, dice: &Dice, loud: bool, json: bool)
+        -> Result<(), DistributionError> {
+    let stats = || -> Result<Vec<(&str, String)>, DistributionError> {
+        let mut stats = vec![
+            ("Mean", format!("{:.2}", dice.mean()?)),
+            ("Variance", format!("{:.2}", dice.variance()?)),
+            ("Std Dev", format!("{:.2}", dice.std_dev()?)),
+            ("Min", dice.min()?.to_string()),
+            ("Max", dice.max()?.to_string()),
+            ("Median", dice.median()?.to_string()),
+        ];
+        for (name, p) in [("10th", 10.0), ("25th", 25.0), ("75th", 75.0), ("90th", 90.0)] {
+            stats.push((name, dice.percentile(p)?.to_string()));
+        }
+        return Ok(stats);
+    };
+    let stats = stats()?;
+    if json {
+        let mut fields = vec![format!("\"expression\":{}", json_string(text)),
+            format!("\"canonical\":{}", json_string(&dice.expr().to_string()))];
+        for (name, value) in stats {
+            let key = name.to_lowercase().replace(' ', "_");
+            fields.push(format!("{}:{}", json_string(&key), value));
+        }
+        println!("{{{}}}", fields.join(","));
+        return Ok(());
+    }
+    if loud {
+        println!("{}", dice);
+    }
+    for (name, value) in stats {
+        if loud {
+            println!("  {:10}{}", format!("{}:", name), value);
+        } else {
+            println!("{}", value);
+        }
+    }
+    return Ok(());
+}
+// Parse the six scores given to point buy
+fn parse_point_buy(text: &str) -> Result<[u8; 6], String> {
+    let mut nums = vec![];
+    for s in text.split_whitespace() {
+        match s.parse::<u8>() {
+            Ok(n) => nums.push(n),
+            Err(_) => return Err(format!("Invalid score '{}'", s)),
+        }
+    }
+    return nums.try_into().map_err(|_| String::from("Six scores must be provided to point buy"));
+}
+// Print a set of statistics generated with the provided method, rerolled until they meet any
+// constraints given, or bought with points by 'pointbuy' followed by the scores, and assigned to
+// abilities if an assignment is given
+fn print_stats(index: u16, method: &str, rng: &mut StdRng, constraints: Option<&Constraints>,
+        assignment: Option<&Assignment>, loud: bool, json: bool) -> Result<(), String> {
+    let mut attempts = None;
+    let (scores, points) = match method.strip_prefix("pointbuy") {
+        Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+            // Bought scores are chosen, so there is nothing to reroll until they meet constraints
+            if constraints.is_some() {
+                return Err(String::from("Constraints cannot be used with point buy"));
+            }
+            let nums = parse_point_buy(rest)?;
+            let rules = PointBuy::default();
+            let scores = rules.buy(nums).map_err(|e| e.to_string())?;
+            let spent = rules.spent(&nums).map_err(|e| e.to_string())?;
+            (scores, Some((spent, rules.budget() - spent)))
+        },
+        _ => match constraints {
+            Some(c) => {
+                let (scores, n) = c.roll_with(method, rng).map_err(|e| e.to_string())?;
+                attempts = Some(n);
+                (scores, None)
+            },
+            None => (Scores::roll_with(method, rng).map_err(|e| e.to_string())?, None),
+        },
+    };
+    if json {
+        let name = if points.is_some() { "pointbuy" } else { method };
+        let points = match points {
+            Some((spent, left)) => format!(",\"spent\":{},\"left\":{}", spent, left),
+            None => String::new(),
+        };
+        let attempts = match attempts {
+            Some(n) => format!(",\"attempts\":{}", n),
+            None => String::new(),
+        };
+        let abilities = match assignment {
+            Some(a) => format!(",\"abilities\":[{}]", scores.assign(a).iter()
+                .map(|(a, score, modifier)| format!(
+                    "{{\"ability\":{},\"score\":{},\"modifier\":{}}}", json_string(a.name()),
+                    score, modifier))
+                .collect::<Vec<String>>().join(",")),
+            None => String::new(),
+        };
+        println!("{{\"index\":{},\"method\":{},\"scores\":{}{}{}{}}}", index,
+            json_string(name), json_list(scores.values()), points, attempts, abilities);
+    } else {
+        match assignment {
+            Some(a) => println!("{}", scores.assign(a)),
+            None => println!("{}", scores),
+        }
+        if let (Some((spent, left)), true) = (points, loud) {
+            println!("Points: {} spent, {} left", spent, left);
+        }
+        if let (Some(n), true) = (attempts, loud) {
+            println!("Attempts: {}", n);
+        }
+    }
+    return Ok(());
+}
+// Log of every die rolled, with critical hits in green and fumbles in red
+fn crit_log(roll: &DiceRoll) -> String {
+    roll.full_log_with(|d| match d.crit {
+        Crit::Hit => d.to_string().green().to_string(),
+        Crit::Fumble => d.to_string().red().to_string(),
+        Crit::None => d.to_string(),
+    })
+}
+// Roll dice with the values of variables and print the result, returning whether the roll passed
+// its check if it had one
+fn print_roll(index: u16, text: &str, dice: &mut Dice, context: &HashMap<String, i64>,
+        rng: &mut StdRng, loud: bool, json: bool) -> Result<Option<bool>, RollError> {
+    let roll = dice.roll_detailed_with_context(context, rng)?;
+    if json {
+        println!("{}", json_roll(index, text, dice, &roll));
+        return Ok(roll.passed());
+    }
+    if loud {
+        print!("{} ", dice);
+        println!("{}", crit_log(&roll));
+        print!("Result: ");
+    }
+    match (roll.passed(), roll.target()) {
+        (Some(passed), Some(target)) if loud => {
+            let outcome = if passed { "success" } else { "failure" };
+            println!("{} ({} vs DC {})", roll.value, outcome, target);
+        },
+        _ => println!("{}", roll.value),
+    }
+    return Ok(roll.passed());
+}
+
+
+// Roll dice read from each line of input, keeping the history of every dice rolled
+#[allow(clippy::too_many_arguments)]
+fn repl(rng: &mut StdRng, roll_mode: &str, crit_range: u32, crit: Option<CritMode>,
+        constraints: Option<&Constraints>, assignment: Option<&Assignment>, loud: bool, json: bool,
+        mut context: HashMap<String, i64>) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(e) => e,
+        Err(e) => {
+            err!(e);
+        },
+    };
+    // Print errors without exiting
+    let warn = |e: &dyn std::fmt::Display| eprintln!("{} {}", "Error:".red(), e);
+    // Dice rolled this session, with the last rolled at the end
+    let mut session: Vec<(String, Dice)> = vec![];
+    let mut index: u16 = 0;
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(l) => l,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                err!(e);
+            },
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((c, r)) => (c, r.trim()),
+            None => (line, ""),
+        };
+        match command {
+            ":quit" | ":q" | "exit" | "quit" => break,
+            ":help" | ":h" => print_repl_help(),
+            ":stats" => {
+                if rest.is_empty() {
+                    warn(&"No statistics generation method provided");
+                } else if let Err(e) = print_stats(index, rest, rng, constraints, assignment, loud,
+                        json) {
+                    warn(&e);
+                } else {
+                    index += 1;
+                }
+            },
+            ":analyze" => {
+                let (text, dice) = if rest.is_empty() {
+                    match session.last() {
+                        Some((text, dice)) => (text.clone(), dice.bind(&context)),
+                        None => {
+                            warn(&"No dice provided to analyze");
+                            continue;
+                        },
+                    }
+                } else {
+                    match read_dice(&[rest.to_string()], roll_mode, crit_range, crit) {
+                        Ok((text, dice)) => (text, dice.bind(&context)),
+                        Err(e) => {
+                            warn(&e);
+                            continue;
+                        },
+                    }
+                };
+                let result = match dice {
+                    Ok(dice) => print_analysis(&text, &dice, loud, json),
+                    Err(e) => {
+                        warn(&e);
+                        continue;
+                    },
+                };
+                if let Err(e) = result {
+                    warn(&e);
+                }
+            },
+            ":set" => {
+                if rest.is_empty() {
+                    let mut names: Vec<&String> = context.keys().collect();
+                    names.sort();
+                    for name in names {
+                        println!("@{} = {}", name, context[name]);
+                    }
+                } else {
+                    match parse_variable(rest) {
+                        Some((name, value)) => {
+                            context.insert(name, value);
+                        },
+                        None => warn(&format!("Invalid variable '{}'", rest)),
+                    }
+                }
+            },
+            ":history" => match session.last() {
+                Some((_, dice)) => {
+                    println!("{}", dice);
+                    for (i, roll) in dice.history().iter().enumerate() {
+                        println!("  {:<4}{}= {}", format!("{}:", i + 1), crit_log(roll), roll);
+                    }
+                },
+                None => warn(&"No dice rolled yet"),
+            },
+            _ if command.starts_with(':') => warn(&format!("Unknown command '{}'", command)),
+            _ => {
+                if line != "!!" {
+                    let dice = read_dice(&[line.to_string()], roll_mode, crit_range, crit);
+                    let (text, dice) = match dice {
+                        Ok(d) => d,
+                        Err(e) => {
+                            warn(&e);
+                            continue;
+                        },
+                    };
+                    // Rolling the same dice again continues their history
+                    let canonical = dice.to_string();
+                    match session.iter().position(|(_, d)| d.to_string() == canonical) {
+                        Some(i) => {
+                            let (_, d) = session.remove(i);
+                            session.push((text, d));
+                        },
+                        None => session.push((text, dice)),
+                    }
+                }
+                let result = match session.last_mut() {
+                    Some((text, dice)) => print_roll(index, text, dice, &context, rng, loud, json),
+                    None => {
+                        warn(&"No dice rolled yet");
+                        continue;
+                    },
+                };
+                match result {
+                    Ok(_) => index += 1,
+                    Err(e) => warn(&e),
+                }
+            },
+        }
+    }
+}
+
+
 // Main
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -57,6 +589,17 @@ fn main() {
     let mut dice_args: Vec<String> = vec![];
     let mut num_rolls: u16 = 1;
     let mut loud: bool = true;
+    let mut advantage: bool = false;
+    let mut disadvantage: bool = false;
+    let mut seed: Option<u64> = None;
+    let mut crit_range: u32 = CRIT_RANGE;
+    let mut crit: Option<CritMode> = None;
+    let mut order: Option<Assignment> = None;
+    let mut bonuses: Vec<(Ability, i8)> = vec![];
+    let mut constraints: Option<Constraints> = None;
+    let mut json: bool = false;
+    let mut interactive: bool = false;
+    let mut context: HashMap<String, i64> = HashMap::new();
     // Parse args
     let mut i: usize = 1;
     while i < args.len() {
@@ -79,9 +622,156 @@ fn main() {
         // Stops most printing
         } else if (args[i] == "-q") || (args[i] == "--quiet") {
             loud = false;
+        // Start interactive mode
+        } else if (args[i] == "-i") || (args[i] == "--interactive") {
+            interactive = true;
+        // Roll with advantage or disadvantage
+        } else if args[i] == "--adv" {
+            advantage = true;
+        } else if args[i] == "--dis" {
+            disadvantage = true;
+        // Seed random rolls
+        } else if args[i] == "--seed" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<u64>() {
+                Ok(s) => seed = Some(s),
+                Err(_) => {
+                    err!("Invalid seed", text);
+                },
+            }
+            i += 1
+        // Set the lowest d20 roll which is a critical hit
+        } else if args[i] == "--crit-range" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<u32>() {
+                Ok(n) if (2..=20).contains(&n) => crit_range = n,
+                _ => {
+                    err!("Invalid crit range", text);
+                },
+            }
+            i += 1
+        // Roll critical damage
+        } else if args[i] == "--crit" {
+            crit = Some(crit.unwrap_or_default());
+        } else if args[i] == "--crit-mode" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text {
+                "double" => crit = Some(CritMode::DoubleDice),
+                "max" => crit = Some(CritMode::MaxPlusRoll),
+                "total" => crit = Some(CritMode::DoubleTotal),
+                _ => {
+                    err!("Invalid crit mode", text);
+                },
+            }
+            i += 1
+        // Assign statistics to abilities in the order of a class or an order provided
+        } else if args[i] == "--class" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match Class::from_name(text) {
+                Some(c) => order = Some(Assignment::class(c)),
+                None => {
+                    err!("Invalid class", text);
+                },
+            }
+            i += 1
+        } else if args[i] == "--order" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match parse_order(text) {
+                Some(a) => order = Some(a),
+                None => {
+                    err!("Invalid ability order", text);
+                },
+            }
+            i += 1
+        // Add a bonus to an ability's statistic
+        } else if args[i] == "--bonus" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match parse_bonus(text) {
+                Some(b) => bonuses.push(b),
+                None => {
+                    err!("Invalid bonus", text);
+                },
+            }
+            i += 1
+        // Reroll statistics which do not meet constraints
+        } else if args[i] == "--min-total" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<u32>() {
+                Ok(n) => constraints = Some(constraints.unwrap_or_default().with_min_total(n)),
+                Err(_) => {
+                    err!("Invalid minimum total", text);
+                },
+            }
+            i += 1
+        } else if args[i] == "--min-modifiers" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<i32>() {
+                Ok(n) => constraints = Some(constraints.unwrap_or_default().with_min_modifiers(n)),
+                Err(_) => {
+                    err!("Invalid minimum modifier total", text);
+                },
+            }
+            i += 1
+        } else if args[i] == "--min-highest" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<u8>() {
+                Ok(n) => constraints = Some(constraints.unwrap_or_default().with_min_highest(n)),
+                Err(_) => {
+                    err!("Invalid minimum highest statistic", text);
+                },
+            }
+            i += 1
+        } else if args[i] == "--max-below" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            let parsed = text.split_once(',').and_then(|(k, y)| {
+                Some((k.trim().parse::<usize>().ok()?, y.trim().parse::<u8>().ok()?))
+            });
+            match parsed {
+                Some((k, y)) => {
+                    constraints = Some(constraints.unwrap_or_default().with_max_below(k, y));
+                },
+                None => {
+                    err!("Invalid maximum statistics below", text);
+                },
+            }
+            i += 1
+        } else if args[i] == "--attempts" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text.parse::<u32>() {
+                Ok(n) if n > 0 => {
+                    constraints = Some(constraints.unwrap_or_default().with_max_attempts(n));
+                },
+                _ => {
+                    err!("Invalid number of attempts", text);
+                },
+            }
+            i += 1
+        // Set the value of a variable
+        } else if args[i] == "--set" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match parse_variable(text) {
+                Some((name, value)) => {
+                    context.insert(name, value);
+                },
+                None => {
+                    err!("Invalid variable", text);
+                },
+            }
+            i += 1
+        // Set output format
+        } else if args[i] == "--format" {
+            let text = args.get(i+1).map(|s| s.as_str()).unwrap_or("");
+            match text {
+                "human" => json = false,
+                "json" => json = true,
+                _ => {
+                    err!("Invalid format", text);
+                },
+            }
+            i += 1
         // Concatinate non option parameters
         } else {
-            if (args[i] != "-") && (args[i].chars().nth(0).unwrap() == '-') {
+            if (args[i] != "-") && args[i].starts_with('-') {
                 let result = &args[i].parse::<i32>();
                 if result.is_err() {
                     err!("Invalid option", args[i]);
@@ -92,57 +782,138 @@ fn main() {
         i += 1;
     }
 
-    if dice_args.len() > 0 {
+    let mut rng = match seed {
+        Some(s) => StdRng::seed_from_u64(s),
+        None => StdRng::from_entropy(),
+    };
+    // Bonuses are added to statistics assigned in the usual order of abilities if none is given
+    let assignment = match (order, bonuses.is_empty()) {
+        (None, true) => None,
+        (order, _) => {
+            let mut a = order.unwrap_or_default();
+            for (ability, amount) in bonuses {
+                a.bonus(ability, amount);
+            }
+            Some(a)
+        },
+    };
+    // Advantage and disadvantage cancel out
+    let roll_mode = match (advantage, disadvantage) {
+        (true, false) => "adv",
+        (false, true) => "dis",
+        _ => "",
+    };
+    if interactive || dice_args.first().is_some_and(|a| a == "repl") {
+        if dice_args.len() > usize::from(!interactive) {
+            err!("Unexpected arguments for interactive mode", dice_args.join(" "));
+        }
+        repl(&mut rng, roll_mode, crit_range, crit, constraints.as_ref(), assignment.as_ref(), loud,
+            json, context);
+    } else if !dice_args.is_empty() {
         // Generate statistics
         if dice_args[0] == "stats" {
-            if dice_args.len() == 2 {
-                if loud {
+            // Point buy is followed by the scores bought
+            if dice_args.len() == 2 || dice_args.get(1).is_some_and(|m| m == "pointbuy") {
+                // Checked before the header, so only the error is printed
+                if dice_args[1] == "pointbuy" && constraints.is_some() {
+                    err!("Constraints cannot be used with point buy");
+                }
+                if loud && !json {
                     println!("Stats:");
                 }
-                for _ in 0..num_rolls {
-                    let scores = match Scores::from(&dice_args[1]) {
-                        Ok(s) => s,
-                        Err(_) => {
-                            err!("Unknown statistics generation method");
-                        },
-                    };
-                    println!("{}", scores);
+                let method = dice_args[1..].join(" ");
+                for i in 0..num_rolls {
+                    if let Err(e) = print_stats(i, &method, &mut rng, constraints.as_ref(),
+                        assignment.as_ref(), loud, json) {
+                        err!(e);
+                    }
                 }
             } else if dice_args.len() > 2 {
                 err!("Too many statistics generation methods provided");
             } else {
                 err!("No statistics generation method provided");
             }
-        // Roll dice
-        } else {
-            // Concatinate dice string
-            let mut dice_text: String = "".to_string();
-            let start_arg: usize;
-            if dice_args[0] == "dice" {
-                start_arg = 1;
-            } else {
-                start_arg = 0;
+        // List, add or remove aliases
+        } else if dice_args[0] == "alias" {
+            let mut aliases = read_aliases();
+            match dice_args.get(1).map(|s| s.as_str()) {
+                None | Some("list") => {
+                    if dice_args.len() > 2 {
+                        err!("Too many arguments provided to list aliases");
+                    }
+                    for (name, text) in aliases.iter() {
+                        if json {
+                            println!("{{\"name\":{},\"expression\":{}}}", json_string(name),
+                                json_string(text));
+                        } else if loud {
+                            println!("{}", aliases.dice(name).unwrap());
+                        } else {
+                            println!("{}", name);
+                        }
+                    }
+                },
+                Some("add") => {
+                    if dice_args.len() < 4 {
+                        err!("No alias name and dice provided");
+                    }
+                    let name = &dice_args[2];
+                    if COMMANDS.contains(&name.as_str()) {
+                        err!("Alias name is already a command", name);
+                    }
+                    if let Err(e) = aliases.add(name, dice_args[3..].join(" ")) {
+                        err!(e);
+                    }
+                    write_aliases(&aliases);
+                    if loud {
+                        println!("{}", aliases.dice(name).unwrap());
+                    }
+                },
+                Some("remove") => {
+                    if dice_args.len() != 3 {
+                        err!("One alias name must be provided to remove");
+                    }
+                    if let Err(e) = aliases.remove(&dice_args[2]) {
+                        err!(e);
+                    }
+                    write_aliases(&aliases);
+                },
+                Some(s) => {
+                    err!("Unknown alias command", s);
+                },
             }
-            for i in start_arg..dice_args.len() {
-                for s in dice_args[i].split_whitespace() {
-                    dice_text += &s;
-                }
+        // Analyze dice
+        } else if dice_args[0] == "analyze" {
+            if dice_args.len() == 1 {
+                err!("No dice provided to analyze");
             }
-            let mut dice = match Dice::from(&dice_text) {
+            let (text, dice) = parse_dice(&dice_args[1..], roll_mode, crit_range, crit);
+            let dice = match dice.bind(&context) {
                 Ok(d) => d,
                 Err(e) => {
                     err!(e);
                 },
             };
+            if let Err(e) = print_analysis(&text, &dice, loud, json) {
+                err!(e);
+            }
+        // Roll dice
+        } else {
+            let start_arg: usize = if dice_args[0] == "dice" { 1 } else { 0 };
+            let (text, mut dice) = parse_dice(&dice_args[start_arg..], roll_mode, crit_range,
+                crit);
             // Roll dice
-            for _ in 0..num_rolls {
-                let dice_result = dice.roll();
-                if loud {
-                    print!("{} ", dice);
-                    println!("{}", dice.log(0));
-                    print!("Result: ");
+            let mut failed = false;
+            for i in 0..num_rolls {
+                match print_roll(i, &text, &mut dice, &context, &mut rng, loud, json) {
+                    Ok(passed) => failed |= passed == Some(false),
+                    Err(e) => {
+                        err!(e);
+                    },
                 }
-                println!("{}", dice_result);
+            }
+            // Failed checks exit with a different status than errors
+            if failed {
+                process::exit(2);
             }
         }
     } else {
@@ -3,15 +3,28 @@
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
 use std::fmt;
+use crate::dice::{BinOp, CmpOp, Compare, Die, Expr, Func, Modifier};
+use crate::lex::{tokenize, Span, Token, TokenKind};
 
 
 /// Error from failed parsing of dice from a string
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum DiceParseError {
     InvalidNumber(String),
     InvalidDie(String),
     InvalidChar(char),
     InvalidMath(String),
+    UnbalancedParens(String),
+    /// Token found where it cannot be used, with its character position
+    UnexpectedToken(String, usize),
+    /// Variable without a value when the expression is rolled
+    UnboundVariable(String),
+    /// Function called with the wrong number of arguments
+    InvalidArgs(String),
+    /// Success target of dice used in arithmetic, with the text spaced to check the total instead
+    AmbiguousCompare(String),
+    /// Advantage or disadvantage of an expression without a d20, with the text after it
+    NoD20(String),
 }
 impl fmt::Display for DiceParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -20,123 +33,367 @@ impl fmt::Display for DiceParseError {
             Self::InvalidDie(s) => write!(f, "Invalid die '{}'", s),
             Self::InvalidChar(s) => write!(f, "Invalid character '{}'", s),
             Self::InvalidMath(s) => write!(f, "Invalid expression '{}'", s),
+            Self::UnbalancedParens(s) => write!(f, "Unbalanced parentheses '{}'", s),
+            Self::UnexpectedToken(s, i) => write!(f, "Unexpected '{}' at character {}", s, i),
+            Self::UnboundVariable(s) => write!(f, "No value for variable '@{}'", s),
+            Self::InvalidArgs(s) => write!(f, "Wrong number of arguments in '{}'", s),
+            Self::AmbiguousCompare(s) => write!(f, "Ambiguous comparison, write '{}' to check the \
+                total or put the dice in parentheses to count successes", s),
+            Self::NoD20(s) => write!(f, "Advantage or disadvantage needs a d20 in '{}'", s),
         }
     }
 }
 
-// Type of Dice Set
-#[derive(Debug, Copy, Clone)]
-pub enum ParseKind {
-    Sum,
-    Mult,
-    Die,
-    Const,
+
+// Add a modifier to a die, unless it conflicts with an earlier modifier
+fn modifiers_push(modifiers: &mut Vec<Modifier>, m: Modifier) -> bool {
+    let exploding = |m: &Modifier| matches!(m, Modifier::Explode(_) | Modifier::Compound(_));
+    let reroll = |m: &Modifier| matches!(m, Modifier::RerollOnce(_) | Modifier::RerollRecursive(_));
+    for other in modifiers.iter() {
+        if (m.is_advantage() && other.is_advantage()) || (exploding(&m) && exploding(other))
+                || (reroll(&m) && reroll(other))
+                || (m.is_pool() && std::mem::discriminant(&m) == std::mem::discriminant(other)) {
+            return false;
+        }
+    }
+    // Failures are only counted in a pool with a success target
+    if let Modifier::Failure(_) = m {
+        if !modifiers.iter().any(|o| matches!(o, Modifier::Success(_))) {
+            return false;
+        }
+    }
+    modifiers.push(m);
+    return true;
 }
 
-// State of Parser
+
+// Precedence climbing parser over tokens
 #[derive(Debug)]
-pub struct ParseState {
-    split: Vec<(char, String)>,
-    kind: ParseKind,
-    history: String,
-    current: String,
-    breakchar: char,
-    previous: char,
+pub struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    // Last comparison read as the success target of dice
+    pool: Option<Span>,
 }
-impl ParseState {
-    pub fn new() -> Self {
-        Self {
-            split: vec![],
-            kind: ParseKind::Const,
-            history: String::new(),
-            current: String::new(),
-            breakchar: '+',
-            previous: ' '
+impl<'a> Parser<'a> {
+    /// Parse a full dice expression from text
+    pub fn parse(text: &'a str) -> Result<Expr, DiceParseError> {
+        let mut parser = Self {
+            text,
+            tokens: tokenize(text)?,
+            pos: 0,
+            pool: None,
+        };
+        if parser.tokens.is_empty() {
+            return Err(DiceParseError::InvalidMath(String::from(text)));
+        }
+        // A leading 'adv' or 'dis' applies to every d20 in the expression
+        let advantage = match parser.peek_kind() {
+            Some(TokenKind::Word("adv")) => Some(Modifier::Advantage),
+            Some(TokenKind::Word("dis")) => Some(Modifier::Disadvantage),
+            _ => None,
+        };
+        if advantage.is_some() {
+            parser.advance();
+        }
+        let start = parser.peek().map_or(text.len(), |t| t.span.start);
+        let mut expr = parser.expr(0, true)?;
+        // Applied before reading any comparison, so its target is rolled normally
+        if let Some(m) = advantage {
+            let mut found = false;
+            expr.for_each_die_mut(&mut |d| {
+                if *d.sides == Expr::Num(20) {
+                    if !d.modifiers.iter().any(|m| m.is_advantage()) {
+                        d.modifiers.push(m);
+                    }
+                    found = true;
+                }
+            });
+            if !found {
+                return Err(DiceParseError::NoD20(text[start..].trim_end().to_string()));
+            }
+        }
+        // A comparison after the whole expression checks its total against a target
+        if let Some(TokenKind::Cmp(op)) = parser.peek_kind() {
+            let op = *op;
+            parser.advance();
+            let target = parser.expr(0, true)?;
+            expr = Expr::Check(op, Box::new(expr), Box::new(target));
+        }
+        match parser.peek() {
+            None => Ok(expr),
+            Some(t) if t.kind == TokenKind::RParen => {
+                Err(DiceParseError::UnbalancedParens(String::from(text)))
+            },
+            Some(t) => Err(parser.unexpected(t)),
         }
     }
 
-    pub fn splits(&self) -> &Vec<(char, String)> {
-        &self.split
+    // Token helpers
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|t| &t.kind)
+    }
+    fn advance(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return t;
+    }
+    fn unexpected(&self, token: &Token) -> DiceParseError {
+        let position = self.text[..token.span.start].chars().count() + 1;
+        DiceParseError::UnexpectedToken(self.slice(token.span).to_string(), position)
+    }
+    // Whether the next token directly follows the last token read, without whitespace between
+    fn attached(&self) -> bool {
+        match (self.pos.checked_sub(1).and_then(|i| self.tokens.get(i)), self.peek()) {
+            (Some(last), Some(next)) => last.span.end == next.span.start,
+            _ => false,
+        }
+    }
+    fn slice(&self, span: Span) -> &str {
+        &self.text[span.start..span.end]
+    }
+    // Fail for a dice pool used in arithmetic, which may have been meant to check the total
+    fn operand(&self, e: &Expr) -> Result<(), DiceParseError> {
+        match self.pool {
+            Some(span) if e.is_pool() => Err(DiceParseError::AmbiguousCompare(format!("{} {} {}",
+                self.text[..span.start].trim_end(), self.slice(span),
+                self.text[span.end..].trim_start()))),
+            _ => Ok(()),
+        }
     }
-    pub fn kind(&self) -> ParseKind {
-        self.kind
+    // Error for a die from its first token through the last token read
+    fn invalid_die(&self, start: Span) -> DiceParseError {
+        let last = self.tokens[self.pos.min(self.tokens.len()) - 1].span;
+        DiceParseError::InvalidDie(self.slice(start.join(last)).to_string())
     }
 
-    pub fn parse_from(text: &str) -> Result<Self, char> {
-        //println!("{}", text);
-        let mut state = Self::new();
-        for c in text.chars() {
-            match state.next(c) {
-                Ok(_) => (),
-                Err(_) => return Err(c),
+    // Binary operators at or above a minimum precedence, signed allows a leading '-'
+    fn expr(&mut self, min_prec: u8, signed: bool) -> Result<Expr, DiceParseError> {
+        let mut lhs = self.unary(signed)?;
+        loop {
+            let op = match self.peek_kind() {
+                Some(TokenKind::Plus) => BinOp::Add,
+                Some(TokenKind::Minus) => BinOp::Sub,
+                Some(TokenKind::Star) => BinOp::Mul,
+                Some(TokenKind::Slash(op)) => *op,
+                _ => break,
+            };
+            if op.precedence() < min_prec {
+                break;
             }
+            self.operand(&lhs)?;
+            self.advance();
+            let rhs = self.expr(op.precedence() + 1, !matches!(op, BinOp::Add | BinOp::Sub))?;
+            self.operand(&rhs)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
         }
-        state.close();
-        return Ok(state);
+        return Ok(lhs);
     }
-    fn next(&mut self, c: char) -> Result<(), ()> {
-        if c == '+' || (c == '-' && self.previous != '*') {
-            match self.kind {
-                ParseKind::Sum => {
-                    self.split.push((self.breakchar, self.current.to_string()));
-                    self.current = String::new();
-                    self.breakchar = c;
-                },
-                _ => {
-                    if self.history != "" {
-                        self.split = vec![('+', self.history.to_string())];
+    // Optional negation, which cannot be repeated or follow '+' or '-'
+    fn unary(&mut self, signed: bool) -> Result<Expr, DiceParseError> {
+        if self.peek_kind() == Some(&TokenKind::Minus) {
+            let minus = self.advance().unwrap();
+            if !signed {
+                return Err(self.unexpected(&minus));
+            }
+            let e = self.unary(false)?;
+            self.operand(&e)?;
+            return Ok(Expr::Neg(Box::new(e)));
+        }
+        return self.die();
+    }
+    // Dice set or a single atom
+    fn die(&mut self) -> Result<Expr, DiceParseError> {
+        let start = match self.peek() {
+            Some(t) => t.span,
+            None => return Err(DiceParseError::InvalidMath(String::from(self.text))),
+        };
+        let number = if self.peek_kind() == Some(&TokenKind::Die) {
+            Expr::Num(1)
+        } else {
+            let atom = self.atom()?;
+            if self.peek_kind() != Some(&TokenKind::Die) {
+                return Ok(atom);
+            }
+            self.dice_number(&atom, start)?
+        };
+        self.advance();
+        let sides = match self.peek() {
+            Some(t) => t.span,
+            None => return Err(self.invalid_die(start)),
+        };
+        let sides = match self.peek_kind() {
+            Some(TokenKind::Num(_) | TokenKind::Var(_) | TokenKind::LParen) => {
+                let atom = self.atom()?;
+                self.dice_number(&atom, sides)?
+            },
+            Some(TokenKind::Word(w)) if Func::from_name(w).is_some() => self.atom()?,
+            Some(_) => {
+                self.advance();
+                return Err(self.invalid_die(start));
+            },
+            None => return Err(self.invalid_die(start)),
+        };
+        let mut modifiers: Vec<Modifier> = vec![];
+        loop {
+            let modifier = match self.peek_kind() {
+                Some(TokenKind::Bang) => {
+                    self.advance();
+                    let compound = self.peek_kind() == Some(&TokenKind::Bang);
+                    if compound {
+                        self.advance();
+                    }
+                    let target = if self.attached() { self.compare()? } else { None };
+                    if compound {
+                        Modifier::Compound(target)
+                    } else {
+                        Modifier::Explode(target)
                     }
-                    self.current = String::new();
-                    self.breakchar = c;
-                    self.kind = ParseKind::Sum;
                 },
-            };
-        } else if c == '*' || c == 'x' {
-            match self.kind {
-                ParseKind::Sum => self.current.push(c),
-                ParseKind::Mult => {
-                    self.split.push(('*', self.current.to_string()));
-                    self.current = String::new();
-                    self.breakchar = '*';
+                Some(TokenKind::Word(w)) => {
+                    let w = *w;
+                    self.advance();
+                    match w {
+                        "kh" => Modifier::KeepHighest(self.count()?),
+                        "kl" => Modifier::KeepLowest(self.count()?),
+                        "dh" => Modifier::DropHighest(self.count()?),
+                        "dl" => Modifier::DropLowest(self.count()?),
+                        "adv" => Modifier::Advantage,
+                        "dis" => Modifier::Disadvantage,
+                        "r" | "ro" => Modifier::RerollOnce(self.target()?),
+                        "rr" => Modifier::RerollRecursive(self.target()?),
+                        "f" => Modifier::Failure(self.target()?),
+                        _ => {
+                            self.pos -= 1;
+                            break;
+                        },
+                    }
                 },
-                _ => {
-                    self.split = vec![('*', self.history.to_string())];
-                    self.current = String::new();
-                    self.breakchar = '*';
-                    self.kind = ParseKind::Mult;
+                // Comparisons after a space check the total of the expression instead
+                Some(TokenKind::Cmp(_)) if self.attached() => {
+                    self.pool = self.peek().map(|t| t.span);
+                    match self.compare()? {
+                        Some(c) => Modifier::Success(c),
+                        None => break,
+                    }
                 },
+                _ => break,
             };
-        } else if c == 'd' || c == 'D' {
-            match self.kind {
-                ParseKind::Sum => self.current.push('d'),
-                ParseKind::Mult => self.current.push('d'),
-                ParseKind::Die => {
-                    self.split.push(('d', self.current.to_string()));
-                    self.current = String::new();
-                    self.breakchar = 'd';
-                },
-                ParseKind::Const => {
-                    self.split = vec![('d', self.history.to_string())];
-                    self.current = String::new();
-                    self.breakchar = 'd';
-                    self.kind = ParseKind::Die;
-                },
+            if !modifiers_push(&mut modifiers, modifier) {
+                return Err(self.invalid_die(start));
+            }
+        }
+        if self.peek_kind() == Some(&TokenKind::Die) {
+            self.advance();
+            return Err(self.invalid_die(start));
+        }
+        return Ok(Expr::Die(Die {
+            number: Box::new(number),
+            sides: Box::new(sides),
+            modifiers,
+        }));
+    }
+    // Quantity or size of dice, failing for numbers written too large to roll
+    fn dice_number(&self, e: &Expr, span: Span) -> Result<Expr, DiceParseError> {
+        match e {
+            Expr::Num(n) if *n > i64::from(u32::MAX) => {
+                Err(DiceParseError::InvalidNumber(self.slice(span).to_string()))
+            },
+            _ => Ok(e.clone()),
+        }
+    }
+    // Optional count following a modifier, defaulting to 1
+    fn count(&mut self) -> Result<u32, DiceParseError> {
+        if let Some(TokenKind::Num(n)) = self.peek_kind() {
+            let n = *n;
+            let token = self.advance().unwrap();
+            return match u32::try_from(n) {
+                Ok(c) => Ok(c),
+                Err(_) => Err(DiceParseError::InvalidNumber(self.slice(token.span).to_string())),
             };
-        } else if c.is_digit(10) || c == '-' {
-            self.current.push(c);
-        } else if c.is_whitespace() {
-            ();
-        } else {
-            return Err(());
         }
-        if !c.is_whitespace() {
-            self.previous = c;
-            self.history.push(c);
+        return Ok(1);
+    }
+    // Required comparison against a number, where a number alone must be equal to it
+    fn target(&mut self) -> Result<Compare, DiceParseError> {
+        if let Some(TokenKind::Num(n)) = self.peek_kind() {
+            let n = *n;
+            self.advance();
+            return Ok(Compare::new(CmpOp::Eq, n));
+        }
+        match self.compare()? {
+            Some(c) => Ok(c),
+            None => match self.advance() {
+                Some(t) => Err(self.unexpected(&t)),
+                None => Err(DiceParseError::InvalidMath(String::from(self.text))),
+            },
+        }
+    }
+    // Optional comparison against a number
+    fn compare(&mut self) -> Result<Option<Compare>, DiceParseError> {
+        let op = match self.peek_kind() {
+            Some(TokenKind::Cmp(op)) => *op,
+            _ => return Ok(None),
+        };
+        let token = self.advance().unwrap();
+        match self.advance() {
+            Some(Token { kind: TokenKind::Num(n), .. }) => Ok(Some(Compare::new(op, n))),
+            Some(t) => Err(self.unexpected(&t)),
+            None => Err(DiceParseError::InvalidMath(self.slice(token.span).to_string())),
+        }
+    }
+    // Number, variable, function call or parenthesized group
+    fn atom(&mut self) -> Result<Expr, DiceParseError> {
+        let token = match self.advance() {
+            Some(t) => t,
+            None => return Err(DiceParseError::InvalidMath(String::from(self.text))),
+        };
+        match token.kind {
+            TokenKind::Num(n) => Ok(Expr::Num(n)),
+            TokenKind::Var(v) => Ok(Expr::Var(v)),
+            TokenKind::LParen => {
+                let inner = self.expr(0, true)?;
+                match self.advance() {
+                    Some(t) if t.kind == TokenKind::RParen => Ok(Expr::Group(Box::new(inner))),
+                    Some(t) => Err(self.unexpected(&t)),
+                    None => Err(DiceParseError::UnbalancedParens(String::from(self.text))),
+                }
+            },
+            TokenKind::Word(w) => match Func::from_name(w) {
+                Some(func) => self.call(func, token.span),
+                None => Err(self.unexpected(&token)),
+            },
+            TokenKind::RParen => Err(DiceParseError::UnbalancedParens(String::from(self.text))),
+            _ => Err(self.unexpected(&token)),
         }
-        return Ok(());
     }
-    fn close(&mut self) {
-        self.split.push((self.breakchar, self.current.to_string()));
+    // Arguments of a function, in parentheses and separated by commas
+    fn call(&mut self, func: Func, start: Span) -> Result<Expr, DiceParseError> {
+        match self.advance() {
+            Some(t) if t.kind == TokenKind::LParen => (),
+            Some(t) => return Err(self.unexpected(&t)),
+            None => return Err(DiceParseError::InvalidMath(String::from(self.text))),
+        }
+        let mut args = vec![];
+        loop {
+            args.push(self.expr(0, true)?);
+            match self.advance() {
+                Some(t) if t.kind == TokenKind::Comma => (),
+                Some(t) if t.kind == TokenKind::RParen => {
+                    if !func.accepts(args.len()) {
+                        let text = self.slice(start.join(t.span)).to_string();
+                        return Err(DiceParseError::InvalidArgs(text));
+                    }
+                    return Ok(Expr::Call(func, args));
+                },
+                Some(t) => return Err(self.unexpected(&t)),
+                None => return Err(DiceParseError::UnbalancedParens(String::from(self.text))),
+            }
+        }
     }
 }
 
@@ -145,97 +402,246 @@ impl ParseState {
 mod tests {
     use super::*;
 
+    fn num(n: i64) -> Box<Expr> {
+        Box::new(Expr::Num(n))
+    }
+
     #[test]
-    fn test_parse_state_new() {
-        let ps = ParseState::new();
-        assert!(ps.split.len() == 0);
-        assert!(ps.history == "");
-        assert!(ps.current == "");
-        assert!(ps.breakchar == '+');
-        match ps.kind {
-            ParseKind::Const => (),
-            _ => panic!(),
-        }
-    }
-    #[test]
-    fn test_parse_state_next_mult() {
-        let mut ps = ParseState {
-            split: vec![],
-            kind: ParseKind::Const,
-            history: String::from("history"),
-            current: String::from("current"),
-            breakchar: '+',
-            previous: ' ',
-        };
-        ps.next('*').unwrap();
-        assert!(ps.split[0].0 == '*');
-        assert!(ps.split[0].1 == "history");
-        match ps.kind {
-            ParseKind::Mult => (),
-            _ => panic!(),
-        }
-    }
-    #[test]
-    fn test_parse_state_next_sum() {
-        let mut ps = ParseState {
-            split: vec![('-', String::from("4"))],
-            kind: ParseKind::Sum,
-            history: String::from("x"),
-            current: String::from("3"),
-            breakchar: '+',
-            previous: ' ',
-        };
-        ps.next('+').unwrap();
-        assert!(ps.split.len() == 2);
-        assert!(ps.split[1].0 == '+');
-        assert!(ps.split[1].1 == "3");
-    }
-    #[test]
-    fn test_parse_state_next_die() {
-        let mut ps = ParseState {
-            split: vec![('+', String::from("1d4"))],
-            kind: ParseKind::Sum,
-            history: String::from("1d4+"),
-            current: String::from("2"),
-            breakchar: '+',
-            previous: ' ',
-        };
-        ps.next('D').unwrap();
-        assert!(ps.split.len() == 1);
-        assert!(ps.current == "2d");
+    fn test_parser_precedence() {
+        let e = Parser::parse("1 + 2*3 - 4").unwrap();
+        let expected = Expr::Binary(BinOp::Sub,
+            Box::new(Expr::Binary(BinOp::Add, num(1),
+                Box::new(Expr::Binary(BinOp::Mul, num(2), num(3))))),
+            num(4));
+        assert_eq!(e, expected);
     }
     #[test]
-    fn test_parse_state_next_digit() {
-        let mut ps = ParseState::new();
-        ps.current = String::from("1234");
-        ps.next('5').unwrap();
-        assert!(ps.current == "12345");
+    fn test_parser_div() {
+        let e = Parser::parse("1 + 6/-2x3 /^ 4").unwrap();
+        let expected = Expr::Binary(BinOp::Add, num(1),
+            Box::new(Expr::Binary(BinOp::DivUp,
+                Box::new(Expr::Binary(BinOp::Mul,
+                    Box::new(Expr::Binary(BinOp::Div, num(6), Box::new(Expr::Neg(num(2))))),
+                    num(3))),
+                num(4))));
+        assert_eq!(e, expected);
+        assert!(matches!(Parser::parse("1//2"),
+            Err(DiceParseError::UnexpectedToken(s, 3)) if s == "/"));
     }
     #[test]
-    fn test_parse_state_next_space() {
-        let mut ps = ParseState::new();
-        ps.current = String::from("1234");
-        ps.next(' ').unwrap();
-        assert!(ps.current == "1234");
+    fn test_parser_neg() {
+        let e = Parser::parse("-1d8*-4").unwrap();
+        let expected = Expr::Binary(BinOp::Mul,
+            Box::new(Expr::Neg(Box::new(Expr::Die(Die::new(1, 8))))),
+            Box::new(Expr::Neg(num(4))));
+        assert_eq!(e, expected);
     }
     #[test]
-    fn test_parse_state_next_error() {
-        let mut ps = ParseState::new();
-        assert!(ps.next('g').is_err());
+    fn test_parser_die() {
+        let e = Parser::parse("d20").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(1, 20)));
+        let e = Parser::parse("(2)d(1d4)").unwrap();
+        assert_eq!(e, Expr::Die(Die {
+            number: Box::new(Expr::Group(num(2))),
+            sides: Box::new(Expr::Group(Box::new(Expr::Die(Die::new(1, 4))))),
+            modifiers: vec![],
+        }));
     }
     #[test]
-    fn test_parse_state_close() {
-        let mut ps = ParseState {
-            split: vec![('1', String::from("test1"))],
-            kind: ParseKind::Const,
-            history: String::new(),
-            current: String::from("test2"),
-            breakchar: '2',
-            previous: ' ',
-        };
-        ps.close();
-        assert!(ps.split.len() == 2);
-        assert!(ps.split[1].0 == '2');
-        assert!(ps.split[1].1 == "test2");
+    fn test_parser_modifiers() {
+        let e = Parser::parse("4d6kh3").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(4, 6).with_modifier(Modifier::KeepHighest(3))));
+        let e = Parser::parse("2d20kl + 1").unwrap();
+        assert_eq!(e, Expr::Binary(BinOp::Add,
+            Box::new(Expr::Die(Die::new(2, 20).with_modifier(Modifier::KeepLowest(1)))),
+            num(1)));
+        let e = Parser::parse("5d8dh1dl2").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(5, 8).with_modifier(Modifier::DropHighest(1))
+            .with_modifier(Modifier::DropLowest(2))));
+        assert!(matches!(Parser::parse("3kh1"),
+            Err(DiceParseError::UnexpectedToken(s, 2)) if s == "kh"));
+        assert!(matches!(Parser::parse("4d6kh99999999999"),
+            Err(DiceParseError::InvalidNumber(s)) if s == "99999999999"));
+        assert!(matches!(Parser::parse("1d9999999999"),
+            Err(DiceParseError::InvalidNumber(s)) if s == "9999999999"));
+        assert!(matches!(Parser::parse("9999999999d6"),
+            Err(DiceParseError::InvalidNumber(s)) if s == "9999999999"));
+    }
+    #[test]
+    fn test_parser_advantage() {
+        let e = Parser::parse("d20adv").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(1, 20).with_modifier(Modifier::Advantage)));
+        let e = Parser::parse("dis 1d20 + 1d6 + 5").unwrap();
+        assert_eq!(format!("{}", e), "1d20dis + 1d6 + 5");
+        let e = Parser::parse("adv 1d20dis").unwrap();
+        assert_eq!(format!("{}", e), "1d20dis");
+        assert_eq!(Parser::parse("adv 2d6"), Err(DiceParseError::NoD20("2d6".to_string())));
+        assert_eq!(Parser::parse(" dis 1d(10+10) + 4 "),
+            Err(DiceParseError::NoD20("1d(10+10) + 4".to_string())));
+        assert!(matches!(Parser::parse("1d20advdis"),
+            Err(DiceParseError::InvalidDie(s)) if s == "1d20advdis"));
+        assert!(matches!(Parser::parse("1 + adv 1d20"),
+            Err(DiceParseError::UnexpectedToken(s, 5)) if s == "adv"));
+    }
+    #[test]
+    fn test_parser_explode() {
+        let e = Parser::parse("3d6!").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(3, 6).with_modifier(Modifier::Explode(None))));
+        let e = Parser::parse("1d10!!>=9kh1").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(1, 10)
+            .with_modifier(Modifier::Compound(Some(Compare::new(CmpOp::Ge, 9))))
+            .with_modifier(Modifier::KeepHighest(1))));
+        assert_eq!(format!("{}", e), "1d10!!>=9kh1");
+        let e = Parser::parse("4d6kh3!").unwrap();
+        assert_eq!(format!("{}", e), "4d6kh3!");
+        assert!(matches!(Parser::parse("1d6!!!"),
+            Err(DiceParseError::InvalidDie(s)) if s == "1d6!!!"));
+        assert!(matches!(Parser::parse("1d6!>d"),
+            Err(DiceParseError::UnexpectedToken(s, 6)) if s == "d"));
+        assert!(matches!(Parser::parse("1d6!>"), Err(DiceParseError::InvalidMath(_))));
+        assert!(matches!(Parser::parse("3!"), Err(DiceParseError::UnexpectedToken(s, 2)) if s == "!"));
+    }
+    #[test]
+    fn test_parser_reroll() {
+        let e = Parser::parse("2d6r1").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(2, 6)
+            .with_modifier(Modifier::RerollOnce(Compare::new(CmpOp::Eq, 1)))));
+        assert_eq!(format!("{}", e), "2d6r1");
+        let e = Parser::parse("4d6r<3").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(4, 6)
+            .with_modifier(Modifier::RerollOnce(Compare::new(CmpOp::Lt, 3)))));
+        assert_eq!(format!("{}", e), "4d6r<3");
+        assert_eq!(Parser::parse("4d6ro<3").unwrap(), e);
+        assert_eq!(format!("{}", Parser::parse("2d6ro1").unwrap()), "2d6r1");
+        let e = Parser::parse("1d20rr<=2kh1").unwrap();
+        assert_eq!(format!("{}", e), "1d20rr<=2kh1");
+        assert!(matches!(Parser::parse("1d6r"), Err(DiceParseError::InvalidMath(_))));
+        assert!(matches!(Parser::parse("1d6r+1"),
+            Err(DiceParseError::UnexpectedToken(s, 5)) if s == "+"));
+        assert!(matches!(Parser::parse("1d6r1rr2"),
+            Err(DiceParseError::InvalidDie(s)) if s == "1d6r1rr2"));
+    }
+    #[test]
+    fn test_parser_pool() {
+        let e = Parser::parse("10d10>=8").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(10, 10)
+            .with_modifier(Modifier::Success(Compare::new(CmpOp::Ge, 8)))));
+        let e = Parser::parse("6d6>4f1").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(6, 6)
+            .with_modifier(Modifier::Success(Compare::new(CmpOp::Gt, 4)))
+            .with_modifier(Modifier::Failure(Compare::new(CmpOp::Eq, 1)))));
+        assert_eq!(format!("{}", e), "6d6>4f1");
+        let e = Parser::parse("6d6kh4>4!").unwrap();
+        assert_eq!(format!("{}", e), "6d6>4kh4!");
+        assert_eq!(format!("{}", Parser::parse("6d6!>4").unwrap()), "6d6!>4");
+        assert!(matches!(Parser::parse("6d6f1"),
+            Err(DiceParseError::InvalidDie(s)) if s == "6d6f1"));
+        assert!(matches!(Parser::parse("6d6>4>5"),
+            Err(DiceParseError::InvalidDie(s)) if s == "6d6>4>5"));
+        assert!(matches!(Parser::parse("6d6>4f1f2"),
+            Err(DiceParseError::InvalidDie(s)) if s == "6d6>4f1f2"));
+        assert!(matches!(Parser::parse("6d6>+"),
+            Err(DiceParseError::UnexpectedToken(s, 5)) if s == "+"));
+    }
+    #[test]
+    fn test_parser_var() {
+        let var = |v: &str| Box::new(Expr::Var(String::from(v)));
+        let e = Parser::parse("@n d@s + @bonus").unwrap();
+        assert_eq!(e, Expr::Binary(BinOp::Add,
+            Box::new(Expr::Die(Die { number: var("n"), sides: var("s"), modifiers: vec![] })),
+            var("bonus")));
+        assert!(matches!(Parser::parse("1d20 @x"),
+            Err(DiceParseError::UnexpectedToken(s, 6)) if s == "@x"));
+    }
+    #[test]
+    fn test_parser_func() {
+        let e = Parser::parse("max(1, 1d4-1)").unwrap();
+        assert_eq!(e, Expr::Call(Func::Max, vec![Expr::Num(1),
+            Expr::Binary(BinOp::Sub, Box::new(Expr::Die(Die::new(1, 4))), num(1))]));
+        let e = Parser::parse("min(5, floor(@level/2))d6").unwrap();
+        assert_eq!(e, Expr::Die(Die {
+            number: Box::new(Expr::Call(Func::Min, vec![Expr::Num(5),
+                Expr::Call(Func::Floor, vec![Expr::Binary(BinOp::Div,
+                    Box::new(Expr::Var(String::from("level"))), num(2))])])),
+            sides: num(6),
+            modifiers: vec![],
+        }));
+        let e = Parser::parse("2dabs(-4)").unwrap();
+        assert_eq!(e, Expr::Die(Die {
+            number: num(2),
+            sides: Box::new(Expr::Call(Func::Abs, vec![Expr::Neg(num(4))])),
+            modifiers: vec![],
+        }));
+        assert!(matches!(Parser::parse("1 + min(1)"),
+            Err(DiceParseError::InvalidArgs(s)) if s == "min(1)"));
+        assert!(matches!(Parser::parse("clamp(1, 2)"),
+            Err(DiceParseError::InvalidArgs(s)) if s == "clamp(1, 2)"));
+        assert!(matches!(Parser::parse("abs 2"),
+            Err(DiceParseError::UnexpectedToken(s, 5)) if s == "2"));
+        assert!(matches!(Parser::parse("1, 2"),
+            Err(DiceParseError::UnexpectedToken(s, 2)) if s == ","));
+        assert!(matches!(Parser::parse("max(1, 2"), Err(DiceParseError::UnbalancedParens(_))));
+        assert!(matches!(Parser::parse("ceil"), Err(DiceParseError::InvalidMath(_))));
+    }
+    #[test]
+    fn test_parser_check() {
+        let e = Parser::parse("1d20+5 >= 15").unwrap();
+        assert_eq!(e, Expr::Check(CmpOp::Ge,
+            Box::new(Expr::Binary(BinOp::Add, Box::new(Expr::Die(Die::new(1, 20))), num(5))),
+            num(15)));
+        let e = Parser::parse("1d20 < 1d10 + 5").unwrap();
+        assert_eq!(e, Expr::Check(CmpOp::Lt, Box::new(Expr::Die(Die::new(1, 20))),
+            Box::new(Expr::Binary(BinOp::Add, Box::new(Expr::Die(Die::new(1, 10))), num(5)))));
+        let e = Parser::parse("1d20>=15").unwrap();
+        assert_eq!(e, Expr::Die(Die::new(1, 20)
+            .with_modifier(Modifier::Success(Compare::new(CmpOp::Ge, 15)))));
+        assert_eq!(Parser::parse("1d20>=15+5"),
+            Err(DiceParseError::AmbiguousCompare(String::from("1d20 >= 15+5"))));
+        assert_eq!(Parser::parse("-2d6>4"),
+            Err(DiceParseError::AmbiguousCompare(String::from("-2d6 > 4"))));
+        assert_eq!(Parser::parse("1 + (2d6>4)x2 - (1d6 + 3d6>4)"),
+            Err(DiceParseError::AmbiguousCompare(String::from("1 + (2d6>4)x2 - (1d6 + 3d6 > 4)"))));
+        let e = Parser::parse("(2d6>4) + 1").unwrap();
+        assert_eq!(format!("{}", e), "(2d6>4) + 1");
+        let e = Parser::parse("1d6! >4").unwrap();
+        assert_eq!(e, Expr::Check(CmpOp::Gt,
+            Box::new(Expr::Die(Die::new(1, 6).with_modifier(Modifier::Explode(None)))), num(4)));
+        let e = Parser::parse("adv 1d20 = 1d20").unwrap();
+        assert_eq!(format!("{}", e), "1d20adv = 1d20");
+        assert!(matches!(Parser::parse("(1 >= 2)"),
+            Err(DiceParseError::UnexpectedToken(s, 4)) if s == ">="));
+        assert!(matches!(Parser::parse("1 >= 2 >= 3"),
+            Err(DiceParseError::UnexpectedToken(s, 8)) if s == ">="));
+        assert!(matches!(Parser::parse("1d20 + 5 >="), Err(DiceParseError::InvalidMath(_))));
+    }
+    #[test]
+    fn test_parser_group() {
+        let e = Parser::parse("((3))").unwrap();
+        assert_eq!(e, Expr::Group(Box::new(Expr::Group(num(3)))));
+    }
+    #[test]
+    fn test_parser_err_die() {
+        assert!(matches!(Parser::parse("1dd4"), Err(DiceParseError::InvalidDie(s)) if s == "1dd"));
+        assert!(matches!(Parser::parse("1d4d6"), Err(DiceParseError::InvalidDie(s)) if s == "1d4d"));
+        assert!(matches!(Parser::parse("2 + 3d"), Err(DiceParseError::InvalidDie(s)) if s == "3d"));
+    }
+    #[test]
+    fn test_parser_err_unexpected() {
+        assert!(matches!(Parser::parse("1**4"),
+            Err(DiceParseError::UnexpectedToken(s, 3)) if s == "*"));
+        assert!(matches!(Parser::parse("3 + -1"),
+            Err(DiceParseError::UnexpectedToken(s, 5)) if s == "-"));
+        assert!(matches!(Parser::parse("--1"),
+            Err(DiceParseError::UnexpectedToken(s, 2)) if s == "-"));
+        assert!(matches!(Parser::parse("2(3)"),
+            Err(DiceParseError::UnexpectedToken(s, 2)) if s == "("));
+    }
+    #[test]
+    fn test_parser_err_other() {
+        assert!(matches!(Parser::parse(""), Err(DiceParseError::InvalidMath(_))));
+        assert!(matches!(Parser::parse("1 +"), Err(DiceParseError::InvalidMath(_))));
+        assert!(matches!(Parser::parse("(1d6+2"), Err(DiceParseError::UnbalancedParens(_))));
+        assert!(matches!(Parser::parse("1d6)+(2"), Err(DiceParseError::UnbalancedParens(_))));
+        assert!(matches!(Parser::parse("1g4+ 2"), Err(DiceParseError::InvalidChar('g'))));
     }
 }
@@ -2,18 +2,224 @@
 //   URL: https://github.com/pennbauman/dndice-rs
 //   Author:
 //     Penn Bauman (pennbauman@protonmail.com)
-use crate::dice::Die;
+use std::fmt;
+use rand::Rng;
+use crate::Dice;
+use crate::abilities::{Abilities, Assignment};
+use crate::dice::{Die, Modifier, RollError};
+use crate::parse::DiceParseError;
+
+
+/// Default limit on sets of scores rolled while trying to meet constraints
+pub const MAX_ATTEMPTS: u32 = 100;
+
+
+/// Error from generating ability scores
+#[derive(Debug, PartialEq)]
+pub enum ScoresError {
+    /// Method which is neither a preset nor valid dice, with the error from parsing it as dice
+    UnknownMethod(String, DiceParseError),
+    /// Dice which failed to roll, such as dice with a variable
+    Roll(String, RollError),
+    /// Score rolled outside of 1 to 30
+    ImpossibleScore(i64),
+    /// Order to assign scores to abilities which does not have every ability once
+    InvalidOrder(String),
+    /// Constraints which no set of scores met, with the number of sets rolled
+    TooManyAttempts(u32),
+}
+impl fmt::Display for ScoresError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMethod(s, _) => write!(f, "Unknown statistics generation method '{}'", s),
+            Self::Roll(s, e) => write!(f, "{} in statistics generation method '{}'", e, s),
+            Self::ImpossibleScore(n) => write!(f, "Impossible score '{}'", n),
+            Self::InvalidOrder(s) => write!(f, "Invalid ability order '{}'", s),
+            Self::TooManyAttempts(n) => write!(f, "No scores met the constraints in {} attempts", n),
+        }
+    }
+}
+
+
+/// Error from buying ability scores with points
+#[derive(Debug, PartialEq)]
+pub enum PointBuyError {
+    /// Score which cannot be bought
+    InvalidScore(u8),
+    /// Points the scores cost, more than the points available
+    OverBudget(u32, u32),
+}
+impl fmt::Display for PointBuyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidScore(s) => write!(f, "Score cannot be bought '{}'", s),
+            Self::OverBudget(spent, budget) => {
+                write!(f, "Scores cost {} points, more than the {} available", spent, budget)
+            },
+        }
+    }
+}
+
+
+/// Rules for buying ability scores with points, the 5th edition D&D rules of 27 points for scores
+/// from 8 to 15 by default
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointBuy {
+    lowest: u8,
+    costs: Vec<u32>,
+    budget: u32,
+}
+impl PointBuy {
+    /// Rules where each score from the lowest up costs the points at its place in the costs
+    pub fn new(lowest: u8, costs: Vec<u32>, budget: u32) -> Self {
+        Self { lowest, costs, budget }
+    }
+    /// Points available to spend
+    pub fn budget(&self) -> u32 {
+        self.budget
+    }
+    /// Points one score costs, if it can be bought
+    pub fn cost(&self, score: u8) -> Option<u32> {
+        let i = score.checked_sub(self.lowest)?;
+        return self.costs.get(usize::from(i)).copied();
+    }
+    /// Points spent buying scores, failing if any cannot be bought
+    pub fn spent(&self, scores: &[u8]) -> Result<u32, PointBuyError> {
+        let mut total: u32 = 0;
+        for s in scores {
+            let cost = self.cost(*s).ok_or(PointBuyError::InvalidScore(*s))?;
+            total = total.saturating_add(cost);
+        }
+        return Ok(total);
+    }
+    /// Points left after buying scores, failing if they cost more than the budget
+    pub fn left(&self, scores: &[u8]) -> Result<u32, PointBuyError> {
+        let spent = self.spent(scores)?;
+        return self.budget.checked_sub(spent).ok_or(PointBuyError::OverBudget(spent, self.budget));
+    }
+    /// Buy a set of scores, failing if any cannot be bought or they cost more than the budget
+    pub fn buy(&self, scores: [u8; 6]) -> Result<Scores, PointBuyError> {
+        self.left(&scores)?;
+        // Scores outside what can be rolled are never valid, whatever the costs
+        if let Some(s) = scores.iter().find(|s| !(1..=30).contains(*s)) {
+            return Err(PointBuyError::InvalidScore(*s));
+        }
+        return Ok(Scores::new(scores));
+    }
+}
+impl Default for PointBuy {
+    fn default() -> Self {
+        Self::new(8, vec![0, 1, 2, 3, 4, 5, 7, 9], 27)
+    }
+}
+
+
+/// Requirements a whole set of scores must meet, with sets which do not rerolled up to a limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraints {
+    min_total: Option<u32>,
+    min_modifiers: Option<i32>,
+    min_highest: Option<u8>,
+    max_below: Option<(usize, u8)>,
+    max_attempts: u32,
+}
+impl Constraints {
+    /// No constraints, rolling at most `MAX_ATTEMPTS` sets
+    pub fn new() -> Self {
+        Self {
+            min_total: None,
+            min_modifiers: None,
+            min_highest: None,
+            max_below: None,
+            max_attempts: MAX_ATTEMPTS,
+        }
+    }
+    /// Require the scores to sum to at least a total
+    pub fn with_min_total(mut self, total: u32) -> Self {
+        self.min_total = Some(total);
+        return self;
+    }
+    /// Require the modifiers of the scores to sum to at least a total
+    pub fn with_min_modifiers(mut self, total: i32) -> Self {
+        self.min_modifiers = Some(total);
+        return self;
+    }
+    /// Require at least one score to be a number or higher
+    pub fn with_min_highest(mut self, score: u8) -> Self {
+        self.min_highest = Some(score);
+        return self;
+    }
+    /// Allow at most a count of scores below a number
+    pub fn with_max_below(mut self, count: usize, score: u8) -> Self {
+        self.max_below = Some((count, score));
+        return self;
+    }
+    /// Set the most sets of scores rolled before failing, `MAX_ATTEMPTS` by default
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        return self;
+    }
+    /// Whether a set of scores meets every constraint
+    pub fn allows(&self, scores: &Scores) -> bool {
+        let nums = scores.values();
+        if let Some(total) = self.min_total {
+            if nums.iter().map(|s| u32::from(*s)).sum::<u32>() < total {
+                return false;
+            }
+        }
+        if let Some(total) = self.min_modifiers {
+            if nums.iter().map(|s| i32::from(Abilities::modifier_of(*s))).sum::<i32>() < total {
+                return false;
+            }
+        }
+        if let Some(score) = self.min_highest {
+            if nums.iter().all(|s| *s < score) {
+                return false;
+            }
+        }
+        if let Some((count, score)) = self.max_below {
+            if nums.iter().filter(|s| **s < score).count() > count {
+                return false;
+            }
+        }
+        return true;
+    }
+    /// Generate scores with a method, as `Scores::from` does, rerolling until they meet every
+    /// constraint and returning the number of sets rolled
+    pub fn roll<S: ToString>(&self, method: S) -> Result<(Scores, u32), ScoresError> {
+        self.roll_with(method, &mut rand::thread_rng())
+    }
+    /// Generate scores meeting every constraint with a random number generator
+    pub fn roll_with<S: ToString, R: Rng + ?Sized>(&self, method: S, rng: &mut R)
+            -> Result<(Scores, u32), ScoresError> {
+        let method = method.to_string();
+        for attempt in 1..=self.max_attempts {
+            let scores = Scores::roll_with(&method, rng)?;
+            if self.allows(&scores) {
+                return Ok((scores, attempt));
+            }
+        }
+        return Err(ScoresError::TooManyAttempts(self.max_attempts));
+    }
+}
+impl Default for Constraints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 
 /// A set of six ability scores
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Scores {
     nums: [u8; 6],
 }
 impl Scores {
     fn new(mut nums_array: [u8; 6]) -> Self {
         for s in &nums_array {
-            if *s <= 0 {
+            if *s == 0 {
                 panic!("impossible stat")
             }
             if *s > 30 {
@@ -30,48 +236,86 @@ impl Scores {
     /// * `std` or `standard`: use std()
     /// * `d20` or `1d20`: use d20()
     /// * `4d6` or `3d6`: use lowest3_4d6()
-    pub fn from<S: ToString>(method: S) -> Result<Self, ()> {
+    /// * Any other dice, such as `2d6 + 6`: use roll_dice()
+    pub fn from<S: ToString>(method: S) -> Result<Self, ScoresError> {
+        Self::roll_with(method, &mut rand::thread_rng())
+    }
+    /// Return scores generated with a method based on the string given, rolling with a random
+    /// number generator
+    pub fn roll_with<S: ToString, R: Rng + ?Sized>(method: S, rng: &mut R)
+            -> Result<Self, ScoresError> {
         let m = method.to_string();
         if (m == "std") || (m == "standard") {
             Ok(Self::std())
         } else if (m == "d20") || (m == "1d20") {
-            Ok(Self::d20())
+            Ok(Self::d20_with(rng))
         } else if (m == "4d6") || (m == "3d6") {
-            Ok(Self::lowest3_4d6())
+            Ok(Self::lowest3_4d6_with(rng))
         } else {
-            Err(())
+            match Dice::from(&m) {
+                Ok(mut dice) => Self::roll_dice_with(&mut dice, rng),
+                Err(e) => Err(ScoresError::UnknownMethod(m, e)),
+            }
         }
     }
+    /// Return scores generated by rolling dice for each score, failing if any is not from 1 to 30
+    pub fn roll_dice(dice: &mut Dice) -> Result<Self, ScoresError> {
+        Self::roll_dice_with(dice, &mut rand::thread_rng())
+    }
+    /// Return scores generated by rolling dice for each score with a random number generator
+    pub fn roll_dice_with<R: Rng + ?Sized>(dice: &mut Dice, rng: &mut R)
+            -> Result<Self, ScoresError> {
+        let mut stats = [0; 6];
+        for s in stats.iter_mut() {
+            let n = dice.roll_with(rng).map_err(|e| ScoresError::Roll(dice.to_string(), e))?;
+            *s = match u8::try_from(n) {
+                Ok(x) if (1..=30).contains(&x) => x,
+                _ => return Err(ScoresError::ImpossibleScore(n)),
+            };
+        }
+        return Ok(Self::new(stats));
+    }
+    /// Scores from highest to lowest
+    pub fn values(&self) -> &[u8; 6] {
+        &self.nums
+    }
+    /// Assign the scores to abilities
+    pub fn assign(&self, assignment: &Assignment) -> Abilities {
+        assignment.apply(self)
+    }
     /// Return the 5th edition D&D standard ability scores
     pub fn std() -> Self {
         Self::new([15, 14, 13, 12, 10, 8])
     }
+    /// Return scores bought with the 5th edition D&D point buy rules, failing if they cannot be
+    /// bought
+    pub fn point_buy(nums: [u8; 6]) -> Result<Self, PointBuyError> {
+        PointBuy::default().buy(nums)
+    }
     /// Return scores generated by rolling 1d20 for each score
     pub fn d20() -> Self {
+        Self::d20_with(&mut rand::thread_rng())
+    }
+    /// Return scores generated by rolling 1d20 for each score with a random number generator
+    pub fn d20_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
         let dice = Die::new(1, 20);
         let mut stats = [0; 6];
-        for i in 0..6 {
-            stats[i] = dice.roll().num().try_into().unwrap();
+        for s in stats.iter_mut() {
+            *s = dice.roll_with(rng).unwrap().try_into().unwrap();
         }
         return Self::new(stats);
     }
     /// Return scores generated by rolling 4d6 and using the sum of the highest 3 number rolled for each score
     pub fn lowest3_4d6() -> Self {
-        let dice = Die::new(1, 6);
+        Self::lowest3_4d6_with(&mut rand::thread_rng())
+    }
+    /// Return scores generated by rolling 4d6 and using the sum of the highest 3 for each score
+    /// with a random number generator
+    pub fn lowest3_4d6_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let dice = Die::new(4, 6).with_modifier(Modifier::KeepHighest(3));
         let mut stats = [0; 6];
-        for i in 0..6 {
-            let mut sum = 0;
-            let mut min = dice.roll().num();
-            for _ in 0..3 {
-                let temp = dice.roll().num();
-                if temp >= min {
-                    sum += temp;
-                } else {
-                    sum += min;
-                    min = temp;
-                }
-            }
-            stats[i] = sum.try_into().unwrap();
+        for s in stats.iter_mut() {
+            *s = dice.roll_with(rng).unwrap().try_into().unwrap();
         }
         return Self::new(stats);
     }
@@ -96,6 +340,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_scores_1d20() {
         for _ in 1..10 {
             let result = Scores::from("1d20").unwrap().nums;
@@ -106,6 +351,7 @@ mod tests {
         }
     }
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_scores_d20() {
         for _ in 1..10 {
             let result = Scores::from("d20").unwrap().nums;
@@ -117,6 +363,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_scores_4d6() {
         for _ in 1..10 {
             let result = Scores::from("4d6").unwrap().nums;
@@ -127,6 +374,7 @@ mod tests {
         }
     }
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn test_scores_3d6() {
         for _ in 1..10 {
             let result = Scores::from("3d6").unwrap().nums;
@@ -136,4 +384,101 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_scores_roll_with() {
+        use rand::SeedableRng;
+        for method in ["1d20", "4d6"] {
+            let mut a = rand::rngs::StdRng::seed_from_u64(9);
+            let mut b = rand::rngs::StdRng::seed_from_u64(9);
+            for _ in 1..10 {
+                assert_eq!(Scores::roll_with(method, &mut a).unwrap().nums,
+                    Scores::roll_with(method, &mut b).unwrap().nums);
+            }
+        }
+    }
+    #[test]
+    fn test_scores_dice() {
+        for _ in 1..10 {
+            let result = Scores::from("2d6 + 6").unwrap().nums;
+            for r in result {
+                assert!(r >= 8);
+                assert!(r <= 18);
+            }
+        }
+        assert_eq!(Scores::from("5d6kh3 - 3d1").unwrap().nums.len(), 6);
+        let mut dice = Dice::from("1d4 + 10").unwrap();
+        Scores::roll_dice(&mut dice).unwrap();
+        assert_eq!(dice.history().len(), 6);
+    }
+    #[test]
+    fn test_scores_err() {
+        assert_eq!(Scores::from("go").unwrap_err(),
+            ScoresError::UnknownMethod("go".to_string(), DiceParseError::InvalidChar('g')));
+        assert_eq!(Scores::from("1d1 - 1d1").unwrap_err(), ScoresError::ImpossibleScore(0));
+        assert_eq!(Scores::from("31").unwrap_err(), ScoresError::ImpossibleScore(31));
+        assert!(matches!(Scores::from("1d6 + @mod"), Err(ScoresError::Roll(s, _))
+            if s == "1d6 + @mod"));
+        assert_eq!(Scores::from("foo").unwrap_err().to_string(),
+            "Unknown statistics generation method 'foo'");
+    }
+    #[test]
+    fn test_constraints_allows() {
+        let std = Scores::std();
+        assert!(Constraints::new().allows(&std));
+        assert!(Constraints::new().with_min_total(72).allows(&std));
+        assert!(!Constraints::new().with_min_total(73).allows(&std));
+        assert!(Constraints::new().with_min_modifiers(5).allows(&std));
+        assert!(!Constraints::new().with_min_modifiers(6).allows(&std));
+        assert!(Constraints::new().with_min_highest(15).allows(&std));
+        assert!(!Constraints::new().with_min_highest(16).allows(&std));
+        assert!(Constraints::new().with_max_below(2, 12).allows(&std));
+        assert!(!Constraints::new().with_max_below(1, 12).allows(&std));
+    }
+    #[test]
+    fn test_constraints_roll() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(4);
+        let c = Constraints::new().with_min_modifiers(3).with_min_highest(15);
+        for _ in 1..10 {
+            let (scores, attempts) = c.roll_with("4d6", &mut rng).unwrap();
+            assert!(c.allows(&scores));
+            assert!((1..=MAX_ATTEMPTS).contains(&attempts));
+        }
+        assert_eq!(Constraints::new().roll("std").unwrap().1, 1);
+        assert_eq!(Constraints::new().with_min_total(73).with_max_attempts(5).roll("std")
+            .unwrap_err(), ScoresError::TooManyAttempts(5));
+        assert!(matches!(Constraints::new().roll("go"), Err(ScoresError::UnknownMethod(_, _))));
+    }
+    #[test]
+    fn test_scores_point_buy() {
+        assert_eq!(Scores::point_buy([8, 15, 15, 8, 15, 8]).unwrap().nums, [15, 15, 15, 8, 8, 8]);
+        assert_eq!(Scores::point_buy([15, 15, 15, 15, 8, 8]).unwrap_err(),
+            PointBuyError::OverBudget(36, 27));
+        assert_eq!(Scores::point_buy([16, 8, 8, 8, 8, 8]).unwrap_err(),
+            PointBuyError::InvalidScore(16));
+        assert_eq!(Scores::point_buy([15, 7, 8, 8, 8, 8]).unwrap_err(),
+            PointBuyError::InvalidScore(7));
+    }
+    #[test]
+    fn test_point_buy_rules() {
+        let rules = PointBuy::default();
+        assert_eq!(rules.budget(), 27);
+        assert_eq!(rules.cost(14), Some(7));
+        assert_eq!(rules.cost(16), None);
+        assert_eq!(rules.spent(&[15, 14, 13, 12, 10, 8]), Ok(27));
+        assert_eq!(rules.left(&[14, 12, 12, 10, 10, 8]), Ok(8));
+        assert_eq!(rules.left(&[15, 15, 15, 15, 8, 8]), Err(PointBuyError::OverBudget(36, 27)));
+        let rules = PointBuy::new(3, vec![0; 16], 0);
+        assert_eq!(rules.buy([3, 18, 18, 18, 18, 18]).unwrap().nums, [18, 18, 18, 18, 18, 3]);
+        assert_eq!(PointBuy::new(0, vec![0; 9], 0).buy([0, 8, 8, 8, 8, 8]).unwrap_err(),
+            PointBuyError::InvalidScore(0));
+    }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_scores_serde() {
+        let json = serde_json::to_string(&Scores::std()).unwrap();
+        assert_eq!(json, "{\"nums\":[15,14,13,12,10,8]}");
+        let back: Scores = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.nums, Scores::std().nums);
+    }
 }
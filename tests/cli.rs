@@ -7,6 +7,7 @@ use std::process::Command;
 
 
 // Check split string and vector match, numbers are replaced with "" in vec
+#[allow(clippy::comparison_to_empty, clippy::explicit_counter_loop)]
 fn check_pattern(output: String, pattern: Vec<&str>) {
     let mut i = 0;
     for w in output.split_whitespace() {
@@ -115,6 +116,108 @@ fn test_stats_4d6() -> Result<(), Box<dyn std::error::Error>> {
     check_pattern(output, expected);
     Ok(())
 }
+#[test]
+fn test_stats_dice() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("2d1+6");
+    cmd.assert().success().stdout(predicates::str::diff("Stats:\n 8  8  8  8  8  8\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("4d6kh3").arg("-q");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec![""; 6]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("1d4 - 5");
+    cmd.assert().failure().stderr(predicates::str::contains("Impossible score '-"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("1d4/0");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "Division by zero in '1d4/0' in statistics generation method '1d4/0'"));
+    Ok(())
+}
+#[test]
+fn test_stats_pointbuy() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("8").arg("15").arg("15").arg("8").arg("15").arg("8");
+    cmd.assert().success().stdout(
+        predicates::str::diff("Stats:\n15 15 15  8  8  8\nPoints: 27 spent, 0 left\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("14 12 12 10 10 8").arg("--format").arg("json");
+    cmd.assert().success().stdout(predicates::str::diff(concat!("{\"index\":0,\"method\":\"pointbuy\",",
+        "\"scores\":[14,12,12,10,10,8],\"spent\":19,\"left\":8}\n")));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("15").arg("15").arg("15").arg("15").arg("8").arg("8");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Scores cost 36 points, more than the 27 available"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("16").arg("8").arg("8").arg("8").arg("8").arg("8");
+    cmd.assert().failure().stderr(predicates::str::contains("Score cannot be bought '16'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("15").arg("15");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Six scores must be provided to point buy"));
+    Ok(())
+}
+#[test]
+fn test_stats_abilities() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--class").arg("wizard").arg("--bonus").arg("int+2")
+        .arg("--bonus").arg("con+1");
+    cmd.assert().success().stdout(predicates::str::diff(concat!("Stats:\nSTR  8 (-1)\n",
+        "DEX 13 (+1)\nCON 15 (+2)\nINT 17 (+3)\nWIS 12 (+1)\nCHA 10 (+0)\n")));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--order").arg("dex,con,wis,str,int,cha").arg("-q");
+    cmd.assert().success().stdout(predicates::str::diff(
+        "STR 12 (+1)\nDEX 15 (+2)\nCON 14 (+2)\nINT 10 (+0)\nWIS 13 (+1)\nCHA  8 (-1)\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--bonus").arg("cha-1").arg("--format").arg("json");
+    cmd.assert().success().stdout(predicates::str::contains(concat!(
+        "{\"ability\":\"WIS\",\"score\":10,\"modifier\":0},",
+        "{\"ability\":\"CHA\",\"score\":7,\"modifier\":-2}]}\n")));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--class").arg("artificer");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid class 'artificer'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--order").arg("str,dex");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid ability order 'str,dex'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--bonus").arg("luck+1");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid bonus 'luck+1'"));
+    Ok(())
+}
+#[test]
+fn test_stats_constraints() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("4d6").arg("--min-modifiers").arg("3").arg("--min-highest").arg("15")
+        .arg("--max-below").arg("1,8");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output.clone(), vec!["Stats:", "", "", "", "", "", "", "Attempts:", ""]);
+    let scores: Vec<i32> = output.split_whitespace().skip(1).take(6)
+        .map(|s| s.parse().unwrap()).collect();
+    assert!(scores[0] >= 15);
+    assert!(scores[4] >= 8);
+    assert!(scores.iter().map(|s| (s - 10).div_euclid(2)).sum::<i32>() >= 3);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("2d1+6").arg("--min-total").arg("48").arg("--format").arg("json");
+    cmd.assert().success().stdout(predicates::str::diff(concat!("{\"index\":0,\"method\":\"2d1+6\",",
+        "\"scores\":[8,8,8,8,8,8],\"attempts\":1}\n")));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--min-total").arg("73").arg("--attempts").arg("3");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("No scores met the constraints in 3 attempts"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("4d6").arg("--max-below").arg("2");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid maximum statistics below '2'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("15").arg("15").arg("15").arg("8").arg("8").arg("8")
+        .arg("--min-total").arg("80");
+    cmd.assert().failure().stdout(predicates::str::is_empty()).stderr(
+        predicates::str::contains("Constraints cannot be used with point buy"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("pointbuy").arg("15 15 15 8 8 8").arg("--attempts").arg("5");
+    cmd.assert().failure().stdout(predicates::str::is_empty()).stderr(
+        predicates::str::contains("Constraints cannot be used with point buy"));
+    Ok(())
+}
 
 // Dice
 #[test]
@@ -155,6 +258,270 @@ fn test_dice_command() -> Result<(), Box<dyn std::error::Error>> {
     check_pattern(output, expected);
     Ok(())
 }
+#[test]
+fn test_dice_parentheses() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("(1d6").arg("+").arg("2)*2");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let expected = vec!["(1d6", "+", "2)x2", "|", "", "Result:", ""];
+    check_pattern(output, expected);
+    Ok(())
+}
+#[test]
+fn test_dice_keep() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d20kh1");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let words: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(words[0], "2d20kh1");
+    assert_eq!(words[1], "|");
+    // One kept and one dropped die
+    assert!(words[2].parse::<i32>().is_ok() || words[3].parse::<i32>().is_ok());
+    assert!(words[2].starts_with('[') || words[3].starts_with('['));
+    Ok(())
+}
+#[test]
+fn test_dice_explode() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1!>5").arg("+").arg("1d2!!<1");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let words: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(words[0..3], ["2d1!>5", "+", "1d2!!<1"]);
+    assert_eq!(words[3..6], ["|", "d1:", "1"]);
+    assert_eq!(words[7..9], ["|", "d2:"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("-q").arg("1d4294967295!!>0");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.trim().parse::<i64>()? > i64::from(u32::MAX));
+    Ok(())
+}
+
+#[test]
+fn test_dice_reroll() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d2rr<2").arg("+").arg("1d1ro1");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let words: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(words[0..3], ["2d2rr<2", "+", "1d1r1"]);
+    assert_eq!(words[3..5], ["|", "d2:"]);
+    assert_eq!(words[7..10], ["|", "d1:", "1r1"]);
+    assert_eq!(words[10..12], ["Result:", "5"]);
+    Ok(())
+}
+
+#[test]
+fn test_dice_pool() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("(3d1>=1)").arg("-").arg("(2d1>1f<=1)");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let words: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(words[0..3], ["(3d1>=1)", "-", "(2d1>1f<=1)"]);
+    assert_eq!(words[3..8], ["|", "d1:", "1s", "1s", "1s"]);
+    assert_eq!(words[8..12], ["|", "d1:", "1f", "1f"]);
+    assert_eq!(words[12..14], ["Result:", "5"]);
+    Ok(())
+}
+#[test]
+fn test_dice_division() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("5d1/2").arg("+").arg("5d1/^2").arg("+").arg("5d1/~4");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["5d1/2", "+", "5d1/^2", "+", "5d1/~4", "|", "d1:", "1", "1", "1",
+        "1", "1", "|", "d1:", "1", "1", "1", "1", "1", "|", "d1:", "1", "1", "1", "1", "1",
+        "Result:", "6"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20/(1d1-1)");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Division by zero in '1d20/(1d1 - 1)'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("1d20/(1d2-1)");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Division by zero possible in '1d20/(1d2 - 1)'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d9999999999");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid number '9999999999'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("(1d1*100000000)d6");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Too many dice or sides in '(1d1x100000000)d6'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("9999999999*9999999999");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Result too large in '9999999999x9999999999'"));
+    Ok(())
+}
+#[test]
+fn test_dice_functions() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("max(1,").arg("2d1-3)").arg("+").arg("ceil(@level/2)").arg("--set").arg("level=5");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["max(1,", "2d1", "-", "3)", "+", "ceil(@level/2)", "|", "1", "1",
+        "Result:", "4"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("clamp(1d6, 2, 5)");
+    cmd.assert().success().stdout(predicates::str::contains("Mean:"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("min(1d6)");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Wrong number of arguments in 'min(1d6)'"));
+    Ok(())
+}
+#[test]
+fn test_dice_check() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("3d1").arg("+").arg("5").arg(">=").arg("@dc").arg("--set").arg("dc=8");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["3d1", "+", "5", ">=", "@dc", "|", "1", "1", "1", "Result:", "8",
+        "(success", "vs", "DC", "8)"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d1 > 1");
+    cmd.assert().code(2).stdout(predicates::str::contains("Result: 1 (failure vs DC 1)"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("-q").arg("1d1 < 2");
+    cmd.assert().success().stdout(predicates::str::diff("1\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--format").arg("json").arg("1d1 + 4 >= 5");
+    cmd.assert().success().stdout(predicates::str::contains(
+        "\"total\":5,\"target\":5,\"passed\":true,"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1>=1");
+    cmd.assert().success().stdout(predicates::str::contains("Result: 2\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d1>=2");
+    cmd.assert().success().stdout(predicates::str::contains("Result: 0\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d1 >= 2");
+    cmd.assert().code(2).stdout(predicates::str::contains("Result: 1 (failure vs DC 2)"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20>=15").arg("+").arg("5");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "Ambiguous comparison, write '1d20 >= 15 + 5' to check the total"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2").arg("+").arg("1d20>=15");
+    cmd.assert().failure().stderr(predicates::str::contains("write '2 + 1d20 >= 15'"));
+    Ok(())
+}
+#[test]
+fn test_dice_crit() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d20").arg("--crit-range").arg("19").arg("--seed").arg("2").env("CLICOLOR_FORCE", "1");
+    cmd.assert().success().stdout(predicates::str::contains("2d20 | 2 \u{1b}[32m19\u{1b}[0m \n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d20").arg("--seed").arg("7").env("CLICOLOR_FORCE", "1");
+    cmd.assert().success().stdout(predicates::str::contains("2d20 | 9 \u{1b}[31m1\u{1b}[0m \n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--format").arg("json").arg("2d1 + 1d20").arg("--crit-range").arg("2");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.contains("\"crit\":true,\"fumble\":false,")
+        || output.contains("\"crit\":false,\"fumble\":true,"));
+    assert!(output.contains("\"outcome\":null,\"crit\":\"hit\"}")
+        || output.contains("\"outcome\":null,\"crit\":\"fumble\"}"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20").arg("--crit-range").arg("21");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid crit range '21'"));
+    Ok(())
+}
+#[test]
+fn test_dice_crit_damage() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d6+4").arg("--crit");
+    cmd.assert().success().stdout(predicates::str::contains("4d6 + 4 | "));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1+4").arg("--crit-mode").arg("max").arg("-q");
+    cmd.assert().success().stdout(predicates::str::diff("8\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--crit-mode").arg("total").arg("analyze").arg("1d1 + 2");
+    cmd.assert().success().stdout(predicates::str::contains("2x(1d1 + 2)\n"))
+        .stdout(predicates::str::contains("Mean:     6.00"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20").arg("--crit-mode").arg("triple");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid crit mode 'triple'"));
+    Ok(())
+}
+
+// Analyze
+#[test]
+fn test_analyze() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("2d6").arg("+").arg("3");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines, ["2d6 + 3", "  Mean:     10.00", "  Variance: 5.83", "  Std Dev:  2.42",
+        "  Min:      5", "  Max:      15", "  Median:   10", "  10th:     7", "  25th:     8",
+        "  75th:     12", "  90th:     13"]);
+    Ok(())
+}
+#[test]
+fn test_analyze_quiet() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("1d4").arg("-q");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert_eq!(output, "2.50\n1.25\n1.12\n1\n4\n2\n1\n1\n3\n4\n");
+    Ok(())
+}
+#[test]
+fn test_analyze_err() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("No dice provided to analyze"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("2d6!dl1");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Cannot calculate distribution of '2d6!dl1'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("100d1!");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Cannot calculate distribution of '100d1!'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("10d1!").arg("-q");
+    cmd.assert().success().stdout(predicates::str::starts_with("1010.00\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("10d1!").arg("-q");
+    cmd.assert().success().stdout(predicates::str::diff("1010\n"));
+    Ok(())
+}
+
+// --adv and --dis
+#[test]
+fn test_adv_option() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("+5").arg("--adv");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let words: Vec<&str> = output.split_whitespace().collect();
+    assert_eq!(words[0..3], ["1d20adv", "+", "5"]);
+    assert!(words[5].starts_with('[') || words[4].starts_with('['));
+    Ok(())
+}
+#[test]
+fn test_dis_option() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20").arg("-").arg("1").arg("--dis");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.starts_with("1d20dis - 1 | "));
+    Ok(())
+}
+#[test]
+fn test_adv_option_no_d20() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--adv").arg("2d6");
+    cmd.assert().failure().stdout(predicates::str::is_empty())
+        .stderr(predicates::str::contains("Advantage or disadvantage needs a d20 in '2d6'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("dis").arg("1d8").arg("+").arg("2");
+    cmd.assert().failure()
+        .stderr(predicates::str::contains("Advantage or disadvantage needs a d20 in '1d8 + 2'"));
+    Ok(())
+}
+#[test]
+fn test_adv_dis_option() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("-2").arg("--dis").arg("--adv");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let expected = vec!["1d20", "-", "2", "|", "", "Result:", ""];
+    check_pattern(output, expected);
+    Ok(())
+}
 
 // --number N
 #[test]
@@ -207,3 +574,310 @@ fn test_quiet_option_dice() -> Result<(), Box<dyn std::error::Error>> {
     check_pattern(output, expected);
     Ok(())
 }
+
+// --seed
+#[test]
+fn test_seed_dice() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("4d6kh3").arg("+").arg("1d20!").arg("-n").arg("5").arg("--seed").arg("1234");
+    let first = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--seed").arg("1234").arg("4d6kh3+1d20!").arg("-n").arg("5");
+    let second = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert_eq!(first.lines().count(), 10);
+    assert_eq!(first, second);
+    Ok(())
+}
+#[test]
+fn test_seed_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("4d6").arg("--seed").arg("99").arg("-n").arg("3");
+    let first = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("4d6").arg("--seed").arg("99").arg("-n").arg("3");
+    let second = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert_eq!(first, second);
+    Ok(())
+}
+#[test]
+fn test_seed_invalid() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d6").arg("--seed").arg("abc");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid seed 'abc'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d6").arg("--seed");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid seed"));
+    Ok(())
+}
+
+// --format
+#[test]
+fn test_format_json_dice() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1").arg("+ 3").arg("--format").arg("json").arg("-n").arg("2");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1], concat!("{\"index\":1,\"expression\":\"2d1 + 3\",\"canonical\":\"2d1 + 3\",",
+        "\"total\":5,\"target\":null,\"passed\":null,\"crit\":false,\"fumble\":false,",
+        "\"successes\":null,\"sets\":[{\"size\":1,\"successes\":null,\"dice\":[",
+        "{\"face\":1,\"value\":1,\"rerolled\":[],\"compounded\":[],\"dropped\":false,",
+        "\"exploded\":false,\"outcome\":null,\"crit\":null},",
+        "{\"face\":1,\"value\":1,\"rerolled\":[],\"compounded\":[],\"dropped\":false,",
+        "\"exploded\":false,\"outcome\":null,\"crit\":null}]}]}"));
+    Ok(())
+}
+#[test]
+fn test_format_json_pool() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--format").arg("json").arg("2d1>=1kh1");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.contains("\"total\":1,\"target\":null,\"passed\":null,\"crit\":false,\
+        \"fumble\":false,\"successes\":1,"));
+    assert!(output.contains("\"dropped\":true,\"exploded\":false,\"outcome\":null,"));
+    assert!(output.contains("\"dropped\":false,\"exploded\":false,\"outcome\":\"success\","));
+    Ok(())
+}
+#[test]
+#[cfg(feature = "serde")]
+fn test_format_json_serde() -> Result<(), Box<dyn std::error::Error>> {
+    use rand::SeedableRng;
+    let text = "4d20kh3!>19 + (6d6>4f1)";
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("--seed").arg("3").arg("--format").arg("json").arg(text);
+    let output: serde_json::Value = serde_json::from_slice(&cmd.output().unwrap().stdout)?;
+    // Rolled again with the same seed, each set of dice is written as serde serializes it, with
+    // lowercase outcomes and crits and null for none
+    let mut dice = dndice::Dice::from(text).unwrap();
+    let roll = dice.roll_detailed_with(&mut rand::rngs::StdRng::seed_from_u64(3)).unwrap();
+    assert_eq!(output["total"], roll.value);
+    let sets = output["sets"].as_array().unwrap();
+    assert_eq!(sets.len(), roll.sets().len());
+    for (set, expected) in sets.iter().zip(roll.sets()) {
+        let expected = serde_json::to_value(expected)?;
+        assert_eq!(set["size"], expected["size"]);
+        assert_eq!(set["successes"], expected["successes"]);
+        let dice: Vec<serde_json::Value> = set["dice"].as_array().unwrap().iter().map(|d| {
+            let mut d = d.clone();
+            d.as_object_mut().unwrap().remove("value");
+            return d;
+        }).collect();
+        let expected: Vec<serde_json::Value> = expected["dice"].as_array().unwrap().iter().map(|d| {
+            let mut d = d.clone();
+            for field in ["outcome", "crit"] {
+                d[field] = match d[field].as_str().unwrap() {
+                    "None" => serde_json::Value::Null,
+                    name => serde_json::Value::from(name.to_lowercase()),
+                };
+            }
+            return d;
+        }).collect();
+        assert_eq!(serde_json::Value::from(dice), serde_json::Value::from(expected));
+    }
+    Ok(())
+}
+#[test]
+fn test_format_json_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("stats").arg("std").arg("--format").arg("json");
+    cmd.assert().success().stdout(
+        predicates::str::diff("{\"index\":0,\"method\":\"std\",\"scores\":[15,14,13,12,10,8]}\n"));
+    Ok(())
+}
+#[test]
+fn test_format_json_analyze() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("1d4").arg("--format").arg("json");
+    cmd.assert().success().stdout(
+        predicates::str::diff(concat!("{\"expression\":\"1d4\",\"canonical\":\"1d4\",\"mean\":2.50,",
+            "\"variance\":1.25,\"std_dev\":1.12,\"min\":1,\"max\":4,\"median\":2,\"10th\":1,",
+            "\"25th\":1,\"75th\":3,\"90th\":4}\n")));
+    Ok(())
+}
+#[test]
+fn test_format_human() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1").arg("--format").arg("human");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["2d1", "|", "1", "1", "Result:", "2"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1").arg("--format").arg("xml");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid format 'xml'"));
+    Ok(())
+}
+
+
+// Interactive mode
+#[test]
+fn test_repl_roll() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("dndice")?;
+    cmd.arg("repl").write_stdin("2d1 + 1\n!!\n\n1d1\n2d1+1\n:history\n");
+    cmd.assert().success().stdout(
+        predicates::str::diff(concat!("2d1 + 1 | 1 1 \nResult: 3\n2d1 + 1 | 1 1 \nResult: 3\n",
+            "1d1 | 1 \nResult: 1\n2d1 + 1 | 1 1 \nResult: 3\n2d1 + 1\n  1:  | 1 1 = 3\n",
+            "  2:  | 1 1 = 3\n  3:  | 1 1 = 3\n")));
+    Ok(())
+}
+#[test]
+fn test_repl_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("dndice")?;
+    cmd.arg("-i").arg("-q").write_stdin(":stats std\n1d1\n:analyze\n:analyze 1d4\n:quit\n1d1\n");
+    cmd.assert().success().stdout(
+        predicates::str::diff(concat!("15 14 13 12 10  8\n1\n",
+            "1.00\n0.00\n0.00\n1\n1\n1\n1\n1\n1\n1\n",
+            "2.50\n1.25\n1.12\n1\n4\n2\n1\n1\n3\n4\n")));
+    Ok(())
+}
+#[test]
+fn test_repl_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("dndice")?;
+    cmd.arg("repl").write_stdin("!!\n1dd\n:stats\n:stats 2dd\n:foo\n1d1\n");
+    cmd.assert().success()
+        .stderr(predicates::str::contains("No dice rolled yet"))
+        .stderr(predicates::str::contains("Invalid die '1dd'"))
+        .stderr(predicates::str::contains("No statistics generation method provided"))
+        .stderr(predicates::str::contains("Unknown statistics generation method '2dd'"))
+        .stderr(predicates::str::contains("Unknown command ':foo'"))
+        .stdout(predicates::str::contains("Result: 1"));
+    cmd = assert_cmd::Command::cargo_bin("dndice")?;
+    cmd.arg("repl").arg("1d4");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Unexpected arguments for interactive mode"));
+    Ok(())
+}
+
+
+// Aliases
+// Find an empty config directory for a test
+fn config_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("dndice-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+#[test]
+fn test_alias_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = config_dir("alias-commands");
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("add").arg("longsword").arg("1d20+7");
+    cmd.assert().success().stdout(predicates::str::diff("longsword: 1d20 + 7\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("add").arg("dmg").arg("1d8").arg("+ 4");
+    cmd.assert().success();
+    assert_eq!(std::fs::read_to_string(dir.join("dndice").join("config.toml"))?,
+        "longsword = \"1d20+7\"\ndmg = \"1d8 + 4\"\n");
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias");
+    cmd.assert().success().stdout(predicates::str::diff("longsword: 1d20 + 7\ndmg: 1d8 + 4\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("remove").arg("longsword");
+    cmd.assert().success();
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("list").arg("-q");
+    cmd.assert().success().stdout(predicates::str::diff("dmg\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("--format").arg("json");
+    cmd.assert().success().stdout(
+        predicates::str::diff("{\"name\":\"dmg\",\"expression\":\"1d8 + 4\"}\n"));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+#[test]
+fn test_alias_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = config_dir("alias-errors");
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("add").arg("d20").arg("1d4");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid alias name 'd20'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("add").arg("stats").arg("1d4");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Alias name is already a command 'stats'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("add").arg("bad").arg("1dd");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid die '1dd' in alias 'bad'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("remove").arg("bad");
+    cmd.assert().failure().stderr(predicates::str::contains("Unknown alias 'bad'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("alias").arg("rename");
+    cmd.assert().failure().stderr(predicates::str::contains("Unknown alias command 'rename'"));
+    std::fs::create_dir_all(dir.join("dndice"))?;
+    std::fs::write(dir.join("dndice").join("config.toml"), "a = \"1d4\"\nbad\n")?;
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("a");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid line 2 'bad' in config"));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+#[test]
+fn test_alias_roll() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = config_dir("alias-roll");
+    std::fs::create_dir_all(dir.join("dndice"))?;
+    std::fs::write(dir.join("dndice").join("config.toml"),
+        "[aliases] # Attacks\ngreatsword = \"2d1 + 3\" # two-handed\ndagger = 1d1 + 2 # light\n")?;
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("greatsword");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["greatsword:", "2d1", "+", "3", "|", "1", "1", "Result:", "5"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("analyze").arg("greatsword").arg("-q");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.starts_with("5.00\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("dagger").arg("-q");
+    cmd.assert().success().stdout(predicates::str::diff("3\n"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.env("XDG_CONFIG_HOME", &dir).arg("longsword");
+    cmd.assert().failure().stderr(predicates::str::contains("Invalid character 'l'"));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+
+// Variables
+#[test]
+fn test_set_option() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("2d1 + @str_mod + @prof").arg("--set").arg("str_mod=3").arg("--set").arg("@prof=-1");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    check_pattern(output, vec!["2d1", "+", "@str_mod", "+", "@prof", "|", "1", "1",
+        "Result:", "4"]);
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("@n").arg("d1").arg("x").arg("@m").arg("--set").arg("n=2").arg("--set").arg("m=3");
+    cmd.assert().success().stdout(predicates::str::contains("Result: 6"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("analyze").arg("1d4 + @x").arg("--set").arg("x=2").arg("-q");
+    let output = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+    assert!(output.starts_with("4.50\n"));
+    Ok(())
+}
+#[test]
+fn test_set_option_err() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20 + @prof");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("No value for variable '@prof'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20").arg("--set").arg("prof");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid variable 'prof'"));
+    cmd = Command::cargo_bin("dndice")?;
+    cmd.arg("1d20").arg("--set").arg("prof=two");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("Invalid variable 'prof=two'"));
+    Ok(())
+}
+#[test]
+fn test_repl_set() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = assert_cmd::Command::cargo_bin("dndice")?;
+    cmd.arg("repl").arg("-q").arg("--set").arg("a=1")
+        .write_stdin("1d1 + @a + @b\n:set b=5\n!!\n:set\n");
+    cmd.assert().success()
+        .stdout(predicates::str::diff("7\n@a = 1\n@b = 5\n"))
+        .stderr(predicates::str::contains("No value for variable '@b'"));
+    Ok(())
+}